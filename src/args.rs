@@ -1,6 +1,11 @@
 use std::path::{Path, PathBuf};
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
+use clap::builder::PossibleValuesParser;
 use std::{env, fs};
+use crate::config::{check_header_value, parse_header, Config};
+use crate::file_data::extractor_impl::FileDataExtractorImpl;
+use crate::git::GitSelection;
+use crate::io::registry::ArchiverRegistry;
 use crate::io_utils;
 use crate::io_utils::{to_io_err_with_context};
 
@@ -10,7 +15,40 @@ pub struct Args {
     pub(crate) output_file_path: PathBuf,
     pub(crate) file_type: String,
     pub(crate) patterns: Vec<String>,
+    pub(crate) exclude_patterns: Vec<String>,
     pub(crate) search_dir: PathBuf,
+    /// A file, or `-` for stdin, listing the files to archive instead of scanning.
+    pub(crate) files_from: Option<String>,
+    pub(crate) null_delimited: bool,
+    /// Takes the files from the git repository instead of scanning.
+    pub(crate) git_selection: Option<GitSelection>,
+    /// Reads the files from the git object database at this revision.
+    pub(crate) git_rev: Option<String>,
+    /// The number of earlier commits listed in the repository context, which
+    /// is only added when this is set.
+    pub(crate) git_context: Option<usize>,
+    /// Encodings pinned for the files matching a pattern, as `pattern=label`.
+    pub(crate) encodings: Vec<String>,
+    /// MIME types for file extensions, as `extension=type`.
+    pub(crate) mime_types: Vec<String>,
+    /// The size up to which binaries are included, binaries are left out
+    /// if this is `None`.
+    pub(crate) binary_size_limit: Option<u64>,
+    /// Whether EML archives only use encodings that any mail tooling accepts.
+    pub(crate) strict_rfc: bool,
+    /// Whether the metadata of every file is added to EML archives.
+    pub(crate) metadata: bool,
+    /// The From, To and Subject of EML archives, defaults are used if `None`.
+    pub(crate) from: Option<String>,
+    pub(crate) to: Option<String>,
+    pub(crate) subject: Option<String>,
+    /// Extra headers of EML archives, from the config file first.
+    pub(crate) headers: Vec<(String, String)>,
+    /// The template for the introduction that replaces the fixed sentence.
+    pub(crate) intro: Option<String>,
+    pub(crate) unfuse: bool,
+    pub(crate) jobs: usize,
+    pub(crate) use_ignore_files: bool,
 }
 pub fn parse_args() -> Result<Args, Box<dyn std::error::Error>> {
     let matches = Command::new("filefuser")
@@ -31,18 +69,28 @@ pub fn parse_args() -> Result<Args, Box<dyn std::error::Error>> {
                 .short('t')
                 .long("type")
                 .value_name("TYPE")
-                .help("Sets the type (default: eml)")
+                .help("Sets the archive type (default: eml)")
                 .num_args(1)
-                .default_value("eml"),
+                .default_value("eml")
+                .value_parser(PossibleValuesParser::new(ArchiverRegistry::with_defaults().names()))
+                .ignore_case(true),
         )
         .arg(
             Arg::new("patterns")
                 .short('p')
                 .long("patterns")
                 .value_name("PATTERNS")
-                .help("Comma separated list of glob patterns to match files to process")
+                .help("Comma separated list of glob patterns to match files to process, patterns starting with ! exclude files, the last matching pattern wins")
                 .num_args(1)
-                .required(true),
+                .required_unless_present_any(["unfuse", "files-from", "git-tracked", "git-changed", "git-staged", "git-rev"]),
+        )
+        .arg(
+            Arg::new("exclude")
+                .short('e')
+                .long("exclude")
+                .value_name("PATTERNS")
+                .help("Comma separated list of glob patterns to exclude, these win over --patterns")
+                .num_args(1),
         )
         .arg(
             Arg::new("dir")
@@ -53,6 +101,177 @@ pub fn parse_args() -> Result<Args, Box<dyn std::error::Error>> {
                 .num_args(1)
                 .default_value("."),
         )
+        .arg(
+            Arg::new("files-from")
+                .long("files-from")
+                .value_name("FILE")
+                .help("Reads the files to archive from FILE, or stdin if FILE is -, instead of scanning --dir, when given --patterns filter the listed files")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("null")
+                .short('0')
+                .long("null")
+                .help("Paths given by --files-from are separated by NUL instead of newline")
+                .requires("files-from")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("git-tracked")
+                .long("git-tracked")
+                .help("Only takes files in the git index, --patterns then filter these files")
+                .conflicts_with_all(["files-from", "git-changed", "git-staged"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("git-changed")
+                .long("git-changed")
+                .value_name("REF")
+                .help("Only takes files that differ from REF, including uncommitted and untracked files")
+                .conflicts_with_all(["files-from", "git-staged"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("git-staged")
+                .long("git-staged")
+                .help("Only takes files with changes staged for the next commit")
+                .conflicts_with("files-from")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("git-rev")
+                .long("git-rev")
+                .value_name("REV")
+                .help("Reads the files below --dir as they are at REV from git instead of from the working tree")
+                .conflicts_with_all(["files-from", "git-tracked", "git-changed", "git-staged"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("git-context")
+                .long("git-context")
+                .value_name("COMMITS")
+                .help("Adds the git branch, commit, working tree status and the subjects of the last COMMITS commits to the introduction (default: 5)")
+                .num_args(0..=1)
+                .default_missing_value("5")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("encoding")
+                .long("encoding")
+                .value_name("PATTERN=LABEL")
+                .help("Reads the files matching PATTERN with the encoding LABEL, such as windows-1252, instead of detecting it, can be given multiple times")
+                .num_args(1)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("mime-type")
+                .long("mime-type")
+                .value_name("EXTENSION=TYPE")
+                .help("Sets the MIME type of files with EXTENSION, such as proto=text/x-protobuf, can be given multiple times")
+                .num_args(1)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("include-binaries")
+                .long("include-binaries")
+                .help("Includes binary files as base64 in EML and JSON archives, up to the size given by --max-binary-size")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max-binary-size")
+                .long("max-binary-size")
+                .value_name("SIZE")
+                .help("Sets the largest binary file that is included, in bytes or with a K, M or G suffix (default: 1M)")
+                .num_args(1)
+                .requires("include-binaries")
+                .default_value("1M")
+                .value_parser(parse_size),
+        )
+        .arg(
+            Arg::new("strict-rfc")
+                .long("strict-rfc")
+                .help("Writes EML archives with 7-bit lines only, encoding 8-bit text as quoted-printable or base64")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("metadata")
+                .long("metadata")
+                .help("Adds the size, modification time, permissions, SHA-256 and line count of every file to its EML part")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_name("ADDRESS")
+                .help("Sets the From header of EML archives, such as 'Build <ci@example.com>'")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .value_name("ADDRESS")
+                .help("Sets the To header of EML archives")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("subject")
+                .long("subject")
+                .value_name("TEXT")
+                .help("Sets the Subject header of EML archives")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("header")
+                .long("header")
+                .value_name("NAME: VALUE")
+                .help("Adds a header to EML archives, can be given multiple times")
+                .num_args(1)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("intro")
+                .long("intro")
+                .value_name("TEXT")
+                .help("Replaces the introduction with TEXT, where {context}, {date}, {file_count} and {files} are filled in")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("intro-file")
+                .long("intro-file")
+                .value_name("FILE")
+                .help("Replaces the introduction with the template in FILE, see --intro")
+                .conflicts_with("intro")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Reads from, to, subject, headers, intro and intro_file from a TOML file, flags win over the file")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .value_name("JOBS")
+                .help("Sets the maximum number of files read at the same time (default: number of CPUs)")
+                .num_args(1)
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("no-ignore")
+                .long("no-ignore")
+                .help("Includes files excluded by .gitignore, .ignore and .filefuserignore files")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("unfuse")
+                .short('u')
+                .long("unfuse")
+                .help("Extracts the archive given by --file into the directory given by --dir")
+                .action(ArgAction::SetTrue),
+        )
         .get_matches();
 
 
@@ -82,28 +301,140 @@ pub fn parse_args() -> Result<Args, Box<dyn std::error::Error>> {
 
     let full_file_path = full_parent_path.join(file_path.file_name().unwrap());
 
+    let unfuse = matches.get_flag("unfuse");
+    if unfuse && !full_file_path.is_file() {
+        return Err(format!("archive file '{}' does not exist", full_file_path.display()).into());
+    }
+
     let file_type = matches.get_one::<String>("type").unwrap().clone();
-    let patterns = matches.get_one::<String>("patterns").unwrap().clone();
+
+    let jobs = match matches.get_one::<usize>("jobs") {
+        Some(0) => return Err("jobs must be at least 1".into()),
+        Some(jobs) => *jobs,
+        None => FileDataExtractorImpl::default_jobs(),
+    };
 
     let search_dir = matches.get_one::<String>("dir").unwrap().clone();
 
-    let pattern_vec: Vec<String> = patterns
-            .split(',')
-            .map(|s| s.to_string())
-            .map(|s| s.trim().to_string())
-            .collect();
+    let git_selection = if matches.get_flag("git-tracked") {
+        Some(GitSelection::Tracked)
+    } else if let Some(rev) = matches.get_one::<String>("git-changed") {
+        Some(GitSelection::Changed(rev.clone()))
+    } else if matches.get_flag("git-staged") {
+        Some(GitSelection::Staged)
+    } else {
+        None
+    };
+
+    let pattern_vec = split_patterns(matches.get_one::<String>("patterns"));
+    let exclude_pattern_vec = split_patterns(matches.get_one::<String>("exclude"));
 
     let search_dir_path = Path::new(&search_dir).to_path_buf();
+
+    // when extracting, the target directory is created if needed
+    if unfuse && !search_dir_path.exists() {
+        fs::create_dir_all(&search_dir_path)
+            .map_err(to_io_err_with_context("error creating target dir".to_string()))?;
+    }
+
     if !search_dir_path.exists() {
         return Err(format!("directory '{}' does not exist", search_dir).into());
     }
     let full_search_dir_path = fs::canonicalize(&search_dir_path)
         .map_err(to_io_err_with_context("error making search dir absolute".to_string()))?;
 
+    let config = match matches.get_one::<String>("config") {
+        Some(config_path) => Config::load(Path::new(config_path))?,
+        None => Config::default(),
+    };
+
+    let from = matches.get_one::<String>("from").cloned().or(config.from);
+    let to = matches.get_one::<String>("to").cloned().or(config.to);
+    let subject = matches.get_one::<String>("subject").cloned().or(config.subject);
+    for (name, value) in [("From", &from), ("To", &to), ("Subject", &subject)] {
+        if let Some(value) = value {
+            check_header_value(name, value)?;
+        }
+    }
+
+    let headers = config.headers.iter()
+        .chain(matches.get_many::<String>("header").unwrap_or_default())
+        .map(|header| parse_header(header))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if config.intro.is_some() && config.intro_file.is_some() {
+        return Err("the config sets both intro and intro_file".into());
+    }
+    let intro_file = matches.get_one::<String>("intro-file").map(PathBuf::from);
+    let intro = match (matches.get_one::<String>("intro"), intro_file) {
+        (Some(intro), _) => Some(intro.clone()),
+        (None, Some(intro_file)) => Some(read_intro(&intro_file)?),
+        (None, None) => match (config.intro, config.intro_file) {
+            (Some(intro), _) => Some(intro),
+            (None, Some(intro_file)) => Some(read_intro(&intro_file)?),
+            (None, None) => None,
+        },
+    };
+
     Ok(Args {
         output_file_path: full_file_path,
         file_type,
         patterns: pattern_vec,
+        exclude_patterns: exclude_pattern_vec,
         search_dir: full_search_dir_path,
+        files_from: matches.get_one::<String>("files-from").cloned(),
+        null_delimited: matches.get_flag("null"),
+        git_selection,
+        git_rev: matches.get_one::<String>("git-rev").cloned(),
+        git_context: matches.get_one::<usize>("git-context").copied(),
+        encodings: matches.get_many::<String>("encoding").unwrap_or_default().cloned().collect(),
+        mime_types: matches.get_many::<String>("mime-type").unwrap_or_default().cloned().collect(),
+        binary_size_limit: match matches.get_flag("include-binaries") {
+            true => matches.get_one::<u64>("max-binary-size").copied(),
+            false => None,
+        },
+        strict_rfc: matches.get_flag("strict-rfc"),
+        metadata: matches.get_flag("metadata"),
+        from,
+        to,
+        subject,
+        headers,
+        intro,
+        unfuse,
+        jobs,
+        use_ignore_files: !matches.get_flag("no-ignore"),
     })
-}
\ No newline at end of file
+}
+
+/// Splits a comma separated list of patterns
+fn split_patterns(patterns: Option<&String>) -> Vec<String> {
+    match patterns {
+        Some(patterns) => patterns
+            .split(',')
+            .map(|s| s.to_string())
+            .map(|s| s.trim().to_string())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Reads the template for the introduction from a file
+fn read_intro(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    fs::read_to_string(path)
+        .map_err(|e| format!("failed to read intro file {}: {}", path.display(), e).into())
+}
+
+/// Parses a size in bytes with an optional K, M or G suffix for powers of 1024
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (number, multiplier) = match size.char_indices().last() {
+        Some((index, 'k' | 'K')) => (&size[..index], 1024),
+        Some((index, 'm' | 'M')) => (&size[..index], 1024 * 1024),
+        Some((index, 'g' | 'G')) => (&size[..index], 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+    number.trim().parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("'{}' is not a size like 500K or 2M", size))
+}
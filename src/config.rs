@@ -0,0 +1,143 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+
+/// Headers that filefuser writes itself, so they can not be added as extra
+/// headers. From, To and Subject have their own settings.
+const RESERVED_HEADERS: [&str; 7] = [
+    "from", "to", "subject", "date", "mime-version", "content-type", "content-transfer-encoding",
+];
+
+/// Settings for the archive message read from a TOML file given with
+/// `--config`. Flags on the command line win over the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub(crate) from: Option<String>,
+    pub(crate) to: Option<String>,
+    pub(crate) subject: Option<String>,
+    /// Extra message headers as `Name: value`.
+    pub(crate) headers: Vec<String>,
+    /// A template for the body of the introduction part.
+    pub(crate) intro: Option<String>,
+    /// A file holding the template for the introduction, relative to the
+    /// directory of the config file.
+    pub(crate) intro_file: Option<PathBuf>,
+}
+
+impl Config {
+    /// Reads a config file
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read config {}: {}", path.display(), e))?;
+        let mut config: Config = toml::from_str(&text)
+            .map_err(|e| format!("invalid config {}: {}", path.display(), e))?;
+
+        if let Some(intro_file) = config.intro_file.take() {
+            let config_dir = path.parent().unwrap_or(Path::new(""));
+            config.intro_file = Some(config_dir.join(intro_file));
+        }
+
+        Ok(config)
+    }
+}
+
+/// Parses an extra message header given as `Name: value`. The name must be
+/// a valid header name that filefuser does not write itself, and the value
+/// must fit on one line.
+pub fn parse_header(spec: &str) -> Result<(String, String), String> {
+    let (name, value) = spec.split_once(':')
+        .ok_or_else(|| format!("header '{}' is not of the form Name: value", spec))?;
+    let name = name.trim();
+
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(format!("invalid header name '{}'", name));
+    }
+    if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+        return Err(format!("header '{}' can not be set as an extra header", name));
+    }
+    check_header_value(name, value)?;
+
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+/// Checks that a header value fits on one line, so that it can not end the
+/// header and start another one
+pub fn check_header_value(name: &str, value: &str) -> Result<(), String> {
+    if value.contains(['\r', '\n']) {
+        return Err(format!("the value of header '{}' contains a line break", name));
+    }
+    Ok(())
+}
+
+/// Replaces every `{name}` placeholder in a template with its value.
+/// Placeholders without a value are kept as they are.
+pub fn render_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let value = placeholder.find('}')
+            .and_then(|end| values.iter()
+                .find(|(name, _)| *name == &placeholder[1..end])
+                .map(|(_, value)| (end, value)));
+
+        match value {
+            Some((end, value)) => {
+                rendered.push_str(value);
+                rest = &placeholder[end + 1..];
+            },
+            None => {
+                rendered.push('{');
+                rest = &placeholder[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_resolves_the_intro_file_next_to_the_config() {
+        let dir = std::env::temp_dir().join(format!("filefuser-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("filefuser.toml");
+        std::fs::write(&path, "subject = \"Review\"\nheaders = [\"X-Project: filefuser\"]\nintro_file = \"prompt.md\"\n").unwrap();
+        let config = Config::load(&path).unwrap();
+
+        std::fs::write(&path, "subjekt = \"typo\"\n").unwrap();
+        let unknown_key = Config::load(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(Some("Review".to_string()), config.subject);
+        assert_eq!(vec!["X-Project: filefuser".to_string()], config.headers);
+        assert_eq!(Some(dir.join("prompt.md")), config.intro_file);
+        assert!(unknown_key.is_err());
+    }
+
+    #[test]
+    fn parse_header_rejects_reserved_and_malformed_headers() {
+        assert_eq!(Ok(("X-Ticket".to_string(), "ABC-123".to_string())), parse_header("X-Ticket:  ABC-123 "));
+        assert!(parse_header("Content-Type: text/html").is_err());
+        assert!(parse_header("subject: hi").is_err());
+        assert!(parse_header("X Ticket: 1").is_err());
+        assert!(parse_header("X-Ticket").is_err());
+        assert!(parse_header("X-Ticket: 1\r\nBcc: x@example.com").is_err());
+    }
+
+    #[test]
+    fn render_template_replaces_known_placeholders() {
+        let values = [("file_count", "2"), ("files", "a.rs\nb.rs")];
+        assert_eq!(
+            "Review these 2 files:\na.rs\nb.rs\nfn main() { {unknown} }",
+            render_template("Review these {file_count} files:\n{files}\nfn main() { {unknown} }", &values)
+        );
+    }
+}
@@ -1,21 +1,74 @@
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use ignore::WalkBuilder;
+use log::{info, warn};
+use globset::{GlobSet, GlobSetBuilder};
+use crate::io_utils::{glob_pattern_to_glob, to_glob_path};
 
-use std::path::PathBuf;
-use log::{info};
-use regex::Regex;
-use crate::io_utils::{simple_patterns_to_regexps};
+/// The name of the filefuser specific ignore file. It uses the same syntax as
+/// `.gitignore` and takes precedence over all other ignore files.
+pub const IGNORE_FILE_NAME: &str = ".filefuserignore";
 
-pub fn get_files(dir: &PathBuf, patterns: &[String])
+/// Glob patterns in the order they were given, each either including or
+/// excluding the paths it matches. The last pattern that matches a path
+/// decides, and paths that no pattern matches are excluded.
+pub struct PatternRules {
+    globs: GlobSet,
+    includes: Vec<bool>,
+}
+
+impl PatternRules {
+    /// Compiles the patterns followed by the exclude patterns. Patterns that
+    /// start with `!` exclude instead of include, `\!` matches a literal `!`.
+    /// Exclude patterns come last so that they win over every pattern.
+    pub fn new(patterns: &[String], exclude_patterns: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut builder = GlobSetBuilder::new();
+        let mut includes = Vec::new();
+
+        let rules = patterns.iter()
+            .map(|pattern| match pattern.trim().strip_prefix('!') {
+                Some(negated) => (negated.to_string(), false),
+                None => (pattern.clone(), true),
+            })
+            .chain(exclude_patterns.iter().map(|pattern| (pattern.clone(), false)));
+
+        for (pattern, include) in rules {
+            if pattern.trim().is_empty() {
+                continue;
+            }
+            builder.add(glob_pattern_to_glob(&pattern)?);
+            includes.push(include);
+        }
+
+        Ok(Self { globs: builder.build()?, includes })
+    }
+
+    /// Checks if a path relative to the search directory is included
+    pub fn is_included(&self, glob_path: &str) -> bool {
+        self.globs.matches(glob_path)
+            .into_iter()
+            .max()
+            .map(|index| self.includes[index])
+            .unwrap_or(false)
+    }
+}
+
+/// Finds all files below `dir` that match the patterns. When `use_ignore_files`
+/// is set, files excluded by `.filefuserignore`, `.ignore`, `.gitignore`,
+/// `.git/info/exclude` and the global git excludes file are skipped, with the
+/// same precedence git uses, and the `.git` directory itself is never entered.
+pub fn get_files(dir: &PathBuf, patterns: &[String], exclude_patterns: &[String], use_ignore_files: bool)
     -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
 
-    info!("get_files: compiling {} patterns to regular expressions", patterns.len());
+    info!("get_files: compiling {} glob patterns and {} exclude patterns", patterns.len(), exclude_patterns.len());
     let compiled_patterns =
-        simple_patterns_to_regexps(patterns)?;
+        PatternRules::new(patterns, exclude_patterns)?;
 
-    info!("get_files: searching for files in {:?}", dir);
+    info!("get_files: searching for files in {:?} (ignore files: {})", dir, use_ignore_files);
 
-    let found_files: Vec<PathBuf> = walk_path(dir)
-        .filter(is_file)
-        .filter(matches_patterns(&compiled_patterns))
+    let found_files: Vec<PathBuf> = walk_path(dir, use_ignore_files)
+        .filter(|path| is_file(path))
+        .filter(matches_patterns(dir, &compiled_patterns))
         .collect();
 
     info!("get_files: found: {:?} files", found_files.len());
@@ -23,23 +76,251 @@ pub fn get_files(dir: &PathBuf, patterns: &[String])
     Ok(found_files)
 }
 
-fn is_file(path: &PathBuf) -> bool {
+/// Takes the files listed in `source` instead of scanning `dir`. The source is
+/// a file or `-` for stdin, with one path per line or, when `null_delimited`
+/// is set, paths separated by NUL bytes as written by `git ls-files -z`.
+/// Relative paths are resolved against `dir` and every path must be inside
+/// it. Listed directories are skipped. When patterns are given they filter
+/// the listed files.
+pub fn get_files_from(dir: &Path, source: &str, null_delimited: bool, patterns: &[String], exclude_patterns: &[String])
+    -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+
+    info!("get_files_from: reading file list from {:?}", source);
+    let mut list = Vec::new();
+    if source == "-" {
+        std::io::stdin().read_to_end(&mut list)?;
+    } else {
+        list = std::fs::read(source)
+            .map_err(|e| format!("error reading file list '{}': {}", source, e))?;
+    }
+
+    // listers such as `fd` also write directories, which can not be archived
+    let (directories, listed_files): (Vec<PathBuf>, Vec<PathBuf>) = parse_file_list(dir, &list, null_delimited)?
+        .into_iter()
+        .partition(|path| path.is_dir());
+    for directory in &directories {
+        warn!("get_files_from: skipping listed directory: {:?}", directory);
+    }
+    info!("get_files_from: listed: {:?} files", listed_files.len());
+
+    filter_files(dir, listed_files, patterns, exclude_patterns)
+}
+
+/// Filters files that were selected by other means than scanning `dir` with
+/// the patterns. Without patterns every file is kept unless it is excluded.
+pub fn filter_files(dir: &Path, files: Vec<PathBuf>, patterns: &[String], exclude_patterns: &[String])
+    -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+
+    if patterns.is_empty() && exclude_patterns.is_empty() {
+        return Ok(files);
+    }
+
+    let patterns = if patterns.is_empty() { vec!["*".to_string()] } else { patterns.to_vec() };
+    let compiled_patterns = PatternRules::new(&patterns, exclude_patterns)?;
+
+    let found_files: Vec<PathBuf> = files.into_iter()
+        .filter(matches_patterns(dir, &compiled_patterns))
+        .collect();
+
+    info!("filter_files: found: {:?} files", found_files.len());
+
+    Ok(found_files)
+}
+
+/// Parses a file list into absolute paths below `dir`, skipping empty entries
+/// and duplicates
+fn parse_file_list(dir: &Path, list: &[u8], null_delimited: bool) -> Result<Vec<PathBuf>, String> {
+    let delimiter = if null_delimited { b'\0' } else { b'\n' };
+
+    let mut files = Vec::new();
+    for entry in list.split(|byte| *byte == delimiter) {
+        let entry = std::str::from_utf8(entry)
+            .map_err(|_| format!("file list entry '{}' is not valid UTF-8", String::from_utf8_lossy(entry)))?;
+        let entry = if null_delimited { entry } else { entry.trim_end_matches('\r') };
+        if entry.is_empty() {
+            continue;
+        }
+        files.push(resolve_listed_path(dir, entry)?);
+    }
+
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Resolves a listed path against `dir` without touching the file system, so
+/// that `./` and `..` are handled the same way for files that do not exist
+fn resolve_listed_path(dir: &Path, entry: &str) -> Result<PathBuf, String> {
+    let mut resolved = PathBuf::new();
+    for component in dir.join(entry).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            _ => resolved.push(component),
+        }
+    }
+
+    if !resolved.starts_with(dir) || resolved == dir {
+        return Err(format!("listed path '{}' is not a file inside '{}'", entry, dir.display()));
+    }
+    Ok(resolved)
+}
+
+fn is_file(path: &Path) -> bool {
     path.is_file()
 }
 
-fn matches_patterns<'a>(compiled_patterns: &'a [Regex]) -> impl Fn(&PathBuf) -> bool + 'a {
-    move |path: &PathBuf| compiled_patterns.iter()
-                .any(|re| re.is_match(path.to_str().unwrap()))
+/// Patterns are matched against the path relative to the search directory
+fn matches_patterns<'a>(dir: &'a Path, compiled_patterns: &'a PatternRules) -> impl Fn(&PathBuf) -> bool + 'a {
+    move |path: &PathBuf| to_glob_path(dir, path)
+                .map(|glob_path| compiled_patterns.is_included(&glob_path))
+                .unwrap_or(false)
 }
 
-fn walk_path(path_buf: &PathBuf)
+fn walk_path(path_buf: &PathBuf, use_ignore_files: bool)
              -> impl Iterator<Item = PathBuf> {
-    walkdir::WalkDir::new(path_buf)
-        .into_iter()
+    let mut builder = WalkBuilder::new(path_buf);
+    builder
+        .standard_filters(use_ignore_files)
+        // hidden files are included just like any other file
+        .hidden(false);
+
+    if use_ignore_files {
+        builder
+            .add_custom_ignore_filename(IGNORE_FILE_NAME)
+            .filter_entry(|entry| entry.file_name() != ".git");
+    }
+
+    builder.build()
         .filter_map(|entry| {
-            if entry.is_err() {
-                return None;
+            match entry {
+                Ok(entry) => Some(entry.into_path()),
+                Err(e) => {
+                    warn!("walk_path: skipping entry: {}", e);
+                    None
+                }
             }
-            Some(entry.unwrap().path().to_path_buf())
         })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn create_tree() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("filefuser-dirscan-{}", uuid::Uuid::new_v4()));
+        for sub_dir in [".git/info", "src", "target/debug"] {
+            fs::create_dir_all(dir.join(sub_dir)).unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(dir.join(".git/info/exclude"), "local.rs\n").unwrap();
+        fs::write(dir.join(IGNORE_FILE_NAME), "src/generated.rs\n").unwrap();
+        for file in [".git/config.rs", "src/main.rs", "src/generated.rs", "local.rs", "target/debug/build.rs", "app.log"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        dir
+    }
+
+    fn relative_files(dir: &PathBuf, use_ignore_files: bool) -> Vec<String> {
+        let patterns = vec!["*.rs".to_string(), "*.log".to_string()];
+        let mut files: Vec<String> = get_files(dir, &patterns, &[], use_ignore_files).unwrap()
+            .iter()
+            .map(|path| path.strip_prefix(dir).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn rules(patterns: &[&str], exclude_patterns: &[&str]) -> PatternRules {
+        let to_strings = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        PatternRules::new(&to_strings(patterns), &to_strings(exclude_patterns)).unwrap()
+    }
+
+    #[test]
+    fn pattern_rules_last_match_wins() {
+        let rules = rules(&["*.rs", "!**/tests/**", "!*_generated.rs", "tests/keep.rs"], &[]);
+        assert!(rules.is_included("src/main.rs"));
+        assert!(!rules.is_included("src/tests/it.rs"));
+        assert!(!rules.is_included("src/schema_generated.rs"));
+        assert!(rules.is_included("tests/keep.rs"));
+        assert!(!rules.is_included("README.md"));
+    }
+
+    #[test]
+    fn pattern_rules_excludes_win_over_patterns() {
+        let rules = rules(&["*.rs", "tests/keep.rs"], &["tests/"]);
+        assert!(rules.is_included("src/main.rs"));
+        assert!(!rules.is_included("tests/keep.rs"));
+    }
+
+    #[test]
+    fn pattern_rules_escaped_exclamation_mark_is_literal() {
+        let rules = rules(&["\\!important.txt"], &[]);
+        assert!(rules.is_included("!important.txt"));
+    }
+
+    #[test]
+    fn parse_file_list_resolves_paths_against_dir() {
+        let dir = Path::new("/repo");
+        let list = b"src/main.rs\r\n./README.md\n\nsrc/../Cargo.toml\n/repo/src/main.rs\n";
+        assert_eq!(
+            vec![PathBuf::from("/repo/Cargo.toml"), PathBuf::from("/repo/README.md"), PathBuf::from("/repo/src/main.rs")],
+            parse_file_list(dir, list, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_file_list_splits_on_nul_when_null_delimited() {
+        let dir = Path::new("/repo");
+        let list = b"a file\nwith newline.txt\0b.txt\0";
+        assert_eq!(
+            vec![PathBuf::from("/repo/a file\nwith newline.txt"), PathBuf::from("/repo/b.txt")],
+            parse_file_list(dir, list, true).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_file_list_rejects_paths_outside_dir() {
+        let dir = Path::new("/repo");
+        assert!(parse_file_list(dir, b"../etc/passwd\n", false).is_err());
+        assert!(parse_file_list(dir, b"/etc/passwd\n", false).is_err());
+    }
+
+    #[test]
+    fn get_files_from_filters_listed_files_with_patterns() {
+        let dir = create_tree();
+        let list = dir.join("files.txt");
+        fs::write(&list, "src/main.rs\napp.log\nmissing.rs\nsrc\n").unwrap();
+
+        let all = get_files_from(&dir, list.to_str().unwrap(), false, &[], &[]).unwrap();
+        let filtered = get_files_from(&dir, list.to_str().unwrap(), false, &["*.rs".to_string()], &["missing.rs".to_string()]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vec![dir.join("app.log"), dir.join("missing.rs"), dir.join("src/main.rs")], all);
+        assert_eq!(vec![dir.join("src/main.rs")], filtered);
+    }
+
+    #[test]
+    fn get_files_respects_ignore_files() {
+        let dir = create_tree();
+        let files = relative_files(&dir, true);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vec!["src/main.rs"], files);
+    }
+
+    #[test]
+    fn get_files_without_ignore_files_finds_everything() {
+        let dir = create_tree();
+        let files = relative_files(&dir, false);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            vec![".git/config.rs", "app.log", "local.rs", "src/generated.rs", "src/main.rs", "target/debug/build.rs"],
+            files
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use std::error::Error;
+use std::path::Path;
+use chardetng::EncodingDetector;
+use encoding_rs::{DecoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use globset::{GlobSet, GlobSetBuilder};
+use crate::io_utils::{glob_pattern_to_glob, to_glob_path};
+
+/// The charsets of UTF-32 content, which encoding_rs does not support.
+const UTF_32LE: &str = "UTF-32LE";
+const UTF_32BE: &str = "UTF-32BE";
+
+/// Encodings that are pinned for the files matching a glob pattern, given as
+/// `pattern=label` with a WHATWG encoding label such as `windows-1252` or
+/// `shift_jis`. When several patterns match a file the last one wins.
+#[derive(Debug, Clone)]
+pub struct EncodingRules {
+    globs: GlobSet,
+    encodings: Vec<&'static Encoding>,
+}
+
+impl EncodingRules {
+    /// Creates rules that pin no encodings, so every encoding is detected
+    pub fn empty() -> Self {
+        Self { globs: GlobSet::empty(), encodings: Vec::new() }
+    }
+
+    /// Parses `pattern=label` specifications
+    pub fn parse(specs: &[String]) -> Result<Self, Box<dyn Error>> {
+        let mut builder = GlobSetBuilder::new();
+        let mut encodings = Vec::new();
+
+        for spec in specs {
+            let (pattern, label) = spec.rsplit_once('=')
+                .ok_or_else(|| format!("encoding '{}' is not of the form pattern=label", spec))?;
+            let encoding = Encoding::for_label(label.trim().as_bytes())
+                .ok_or_else(|| format!("unknown encoding '{}' for pattern '{}'", label, pattern))?;
+            builder.add(glob_pattern_to_glob(pattern)?);
+            encodings.push(encoding);
+        }
+
+        Ok(Self { globs: builder.build()?, encodings })
+    }
+
+    /// Returns the encoding pinned for a path relative to the search directory
+    pub fn encoding_for(&self, relative_path: &Path) -> Option<&'static Encoding> {
+        let glob_path = to_glob_path(Path::new(""), relative_path)?;
+        self.globs.matches(glob_path)
+            .into_iter()
+            .max()
+            .map(|index| self.encodings[index])
+    }
+}
+
+/// Checks if the start of a file is text in a pinned encoding. The sample
+/// must decode without errors and, like UTF-8 text, not be mostly control
+/// characters. A multi-byte sequence cut off by the end of the sample is fine.
+pub fn is_text_in(sample: &[u8], encoding: &'static Encoding, is_binary_control: fn(char) -> bool) -> bool {
+    let Some(text) = decode_sample(sample, encoding) else {
+        return false;
+    };
+
+    let non_text = text.chars().filter(|c| is_binary_control(*c) || *c == '\0').count();
+    non_text <= text.chars().count() / 3
+}
+
+/// Decodes the start of a file, returning `None` if it is not valid in the
+/// encoding. A multi-byte sequence cut off by the end of the sample is fine.
+pub fn decode_sample(sample: &[u8], encoding: &'static Encoding) -> Option<String> {
+    let mut decoder = encoding.new_decoder_with_bom_removal();
+    let mut text = String::with_capacity(decoder.max_utf8_buffer_length_without_replacement(sample.len()).unwrap_or(0));
+    let (result, _) = decoder.decode_to_string_without_replacement(sample, &mut text, false);
+    matches!(result, DecoderResult::InputEmpty).then_some(text)
+}
+
+/// Converts text content to UTF-8. Content in a pinned encoding is decoded
+/// with that encoding, otherwise the encoding is taken from a UTF-32 or
+/// UTF-16 byte order mark or detected if the content is not valid UTF-8.
+/// Returns the UTF-8 content and the name of the charset it was converted
+/// from, which is `None` if the content was UTF-8 already.
+pub fn to_utf8(content: Vec<u8>, pinned: Option<&'static Encoding>) -> Result<(Vec<u8>, Option<String>), String> {
+    if pinned.is_none() {
+        if let Some((text, charset)) = decode_utf32(&content) {
+            return Ok((text.into_bytes(), Some(charset.to_string())));
+        }
+    }
+
+    let encoding = match pinned {
+        Some(encoding) => encoding,
+        None => match Encoding::for_bom(&content) {
+            Some((encoding, _)) => encoding,
+            None if std::str::from_utf8(&content).is_ok() => UTF_8,
+            None => {
+                check_not_mostly_utf8(&content)?;
+                let mut detector = EncodingDetector::new();
+                detector.feed(&content, true);
+                detector.guess(None, true)
+            }
+        },
+    };
+
+    if encoding == UTF_8 {
+        return match std::str::from_utf8(&content) {
+            Ok(_) => Ok((content, None)),
+            Err(e) => Err(format!("content is not valid UTF-8: {}", e)),
+        };
+    }
+
+    // a byte order mark is kept out of the text, it is implied by the charset
+    let bom_length = match Encoding::for_bom(&content) {
+        Some((bom_encoding, length)) if bom_encoding == encoding => length,
+        _ => 0,
+    };
+    let text = encoding.decode_without_bom_handling_and_without_replacement(&content[bom_length..])
+        .ok_or_else(|| format!("content is not valid {}", encoding.name()))?;
+
+    Ok((text.into_owned().into_bytes(), Some(encoding.name().to_string())))
+}
+
+/// Fails for UTF-8 content with a few invalid bytes, which a legacy encoding
+/// would turn into mojibake. Content is taken for UTF-8 when it has more valid
+/// multi-byte sequences than invalid bytes.
+fn check_not_mostly_utf8(content: &[u8]) -> Result<(), String> {
+    let mut multi_byte = 0;
+    let mut invalid = 0;
+    let mut first_invalid = None;
+    let mut offset = 0;
+
+    for chunk in content.utf8_chunks() {
+        multi_byte += chunk.valid().chars().filter(|c| !c.is_ascii()).count();
+        offset += chunk.valid().len();
+        if !chunk.invalid().is_empty() {
+            first_invalid.get_or_insert(offset);
+            invalid += chunk.invalid().len();
+            offset += chunk.invalid().len();
+        }
+    }
+
+    match first_invalid {
+        Some(offset) if multi_byte > invalid => Err(format!(
+            "content is UTF-8 with {} invalid bytes, the first at offset {}, pin its encoding with --encoding if it is not UTF-8",
+            invalid, offset
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Decodes content that starts with a UTF-32 byte order mark. Returns `None`
+/// if the content is not valid UTF-32, as `FF FE 00 00` may also be UTF-16LE
+/// content starting with a NUL character.
+fn decode_utf32(content: &[u8]) -> Option<(String, &'static str)> {
+    let (charset, to_u32): (_, fn([u8; 4]) -> u32) = if content.starts_with(&[0xFF, 0xFE, 0x00, 0x00]) {
+        (UTF_32LE, u32::from_le_bytes)
+    } else if content.starts_with(&[0x00, 0x00, 0xFE, 0xFF]) {
+        (UTF_32BE, u32::from_be_bytes)
+    } else {
+        return None;
+    };
+    if !content.len().is_multiple_of(4) {
+        return None;
+    }
+
+    content[4..].chunks_exact(4)
+        .map(|unit| char::from_u32(to_u32([unit[0], unit[1], unit[2], unit[3]])))
+        .collect::<Option<String>>()
+        .map(|text| (text, charset))
+}
+
+/// Converts UTF-8 text back to the charset it was converted from by
+/// [`to_utf8`]. The text must be representable in the charset. A byte order
+/// mark is not added, see [`byte_order_mark`].
+pub fn from_utf8(text: &[u8], charset: &str) -> Result<Vec<u8>, String> {
+    let text = std::str::from_utf8(text)
+        .map_err(|e| format!("content is not valid UTF-8: {}", e))?;
+
+    // encoding_rs does not support UTF-32 at all, so it is encoded here
+    match charset {
+        UTF_32LE => return Ok(text.chars().flat_map(|c| (c as u32).to_le_bytes()).collect()),
+        UTF_32BE => return Ok(text.chars().flat_map(|c| (c as u32).to_be_bytes()).collect()),
+        _ => {}
+    }
+
+    let encoding = Encoding::for_label(charset.as_bytes())
+        .ok_or_else(|| format!("unknown charset '{}'", charset))?;
+
+    // encoding_rs only decodes UTF-16, so it is encoded here
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        return Ok(text.encode_utf16()
+            .flat_map(|unit| if encoding == UTF_16LE { unit.to_le_bytes() } else { unit.to_be_bytes() })
+            .collect());
+    }
+
+    let (encoded, _, has_unmappable) = encoding.encode(text);
+    if has_unmappable {
+        return Err(format!("content can not be represented in {}", encoding.name()));
+    }
+    Ok(encoded.into_owned())
+}
+
+/// Returns the byte order mark of a charset whose byte order mark is removed
+/// by [`to_utf8`]
+pub fn byte_order_mark(charset: &str) -> Option<&'static [u8]> {
+    match charset {
+        UTF_32LE => return Some(b"\xFF\xFE\x00\x00"),
+        UTF_32BE => return Some(b"\x00\x00\xFE\xFF"),
+        _ => {}
+    }
+    match Encoding::for_label(charset.as_bytes()) {
+        Some(encoding) if encoding == UTF_16LE => Some(b"\xFF\xFE"),
+        Some(encoding) if encoding == UTF_16BE => Some(b"\xFE\xFF"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn utf8_content_is_not_converted() {
+        let content = "grüße, 日本語\n".as_bytes().to_vec();
+        assert_eq!((content.clone(), None), to_utf8(content, None).unwrap());
+    }
+
+    #[test]
+    fn windows_1252_content_is_detected_and_converted() {
+        let (content, _, _) = encoding_rs::WINDOWS_1252.encode("Le café crème coûte 3 € à Noël, déjà réglé.\n");
+        let (converted, charset) = to_utf8(content.into_owned(), None).unwrap();
+        assert_eq!("Le café crème coûte 3 € à Noël, déjà réglé.\n", String::from_utf8(converted).unwrap());
+        assert_eq!(Some("windows-1252".to_string()), charset);
+    }
+
+    #[test]
+    fn utf16_content_is_converted_using_its_byte_order_mark() {
+        let content: Vec<u8> = [0xFF, 0xFE].into_iter()
+            .chain("héllo 世界\n".encode_utf16().flat_map(|unit| unit.to_le_bytes()))
+            .collect();
+        let (converted, charset) = to_utf8(content, None).unwrap();
+        assert_eq!("héllo 世界\n", String::from_utf8(converted).unwrap());
+        assert_eq!(Some("UTF-16LE".to_string()), charset);
+    }
+
+    #[test]
+    fn utf32_content_is_converted_using_its_byte_order_mark() {
+        for (mark, charset, to_bytes) in [
+            ([0xFF, 0xFE, 0x00, 0x00], "UTF-32LE", u32::to_le_bytes as fn(u32) -> [u8; 4]),
+            ([0x00, 0x00, 0xFE, 0xFF], "UTF-32BE", u32::to_be_bytes),
+        ] {
+            let content: Vec<u8> = mark.into_iter()
+                .chain("héllo 世界 🦀\n".chars().flat_map(|c| to_bytes(c as u32)))
+                .collect();
+            let (converted, converted_charset) = to_utf8(content.clone(), None).unwrap();
+            assert_eq!("héllo 世界 🦀\n", String::from_utf8(converted.clone()).unwrap());
+            assert_eq!(Some(charset.to_string()), converted_charset);
+            assert_eq!(Some(&mark[..]), byte_order_mark(charset));
+            assert_eq!(&content[4..], from_utf8(&converted, charset).unwrap());
+        }
+
+        // a UTF-16LE byte order mark followed by a NUL character
+        let content = vec![0xFF, 0xFE, 0x00, 0x00, b'a', 0x00];
+        assert_eq!(Some("UTF-16LE".to_string()), to_utf8(content, None).unwrap().1);
+    }
+
+    #[test]
+    fn utf8_content_with_a_stray_byte_is_not_taken_for_a_legacy_encoding() {
+        let mut content = "Grüße aus Köln, schöne Grüße\n".as_bytes().to_vec();
+        content.insert(6, 0xFF);
+        let error = to_utf8(content, None).unwrap_err();
+        assert!(error.contains("1 invalid bytes, the first at offset 6"), "{}", error);
+
+        // legacy text without any valid multi-byte sequence is still detected
+        let (converted, charset) = to_utf8(b"Caf\xe9 cr\xe8me br\xfbl\xe9e, na\xefve fa\xe7ade\n".to_vec(), None).unwrap();
+        assert_eq!("Café crème brûlée, naïve façade\n", String::from_utf8(converted).unwrap());
+        assert_eq!(Some("windows-1252".to_string()), charset);
+    }
+
+    #[test]
+    fn pinned_encoding_is_used_and_checked() {
+        let (content, _, _) = encoding_rs::SHIFT_JIS.encode("設定ファイル\n");
+        let rules = EncodingRules::parse(&["legacy/*.txt=shift_jis".to_string()]).unwrap();
+
+        let pinned = rules.encoding_for(&PathBuf::from("legacy/readme.txt"));
+        assert_eq!(Some(encoding_rs::SHIFT_JIS), pinned);
+        assert_eq!(None, rules.encoding_for(&PathBuf::from("src/readme.txt")));
+
+        let (converted, charset) = to_utf8(content.into_owned(), pinned).unwrap();
+        assert_eq!("設定ファイル\n", String::from_utf8(converted).unwrap());
+        assert_eq!(Some("Shift_JIS".to_string()), charset);
+
+        assert!(to_utf8(vec![0xFF, 0xFF, b'\n'], Some(UTF_8)).is_err());
+    }
+
+    #[test]
+    fn is_text_in_decodes_with_the_pinned_encoding() {
+        let is_control = |c: char| c.is_control() && !c.is_whitespace();
+        let (sample, _, _) = encoding_rs::SHIFT_JIS.encode("設定");
+        assert!(is_text_in(&sample, encoding_rs::SHIFT_JIS, is_control));
+        // cut in the middle of a two byte character
+        assert!(is_text_in(&sample[..3], encoding_rs::SHIFT_JIS, is_control));
+        assert!(!is_text_in(&[0xFF, 0xFF, 0xFF], UTF_8, is_control));
+        assert!(!is_text_in(&[0x00, 0x01, 0x02, b'a'], encoding_rs::WINDOWS_1252, is_control));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_labels() {
+        assert!(EncodingRules::parse(&["*.txt=klingon".to_string()]).is_err());
+        assert!(EncodingRules::parse(&["*.txt".to_string()]).is_err());
+    }
+
+    #[test]
+    fn from_utf8_reverses_the_conversion() {
+        let (content, _, _) = encoding_rs::WINDOWS_1252.encode("Le café crème coûte 3 € à Noël.\n");
+        let (converted, charset) = to_utf8(content.to_vec(), None).unwrap();
+        assert_eq!(content.as_ref(), from_utf8(&converted, &charset.unwrap()).unwrap());
+
+        let content: Vec<u8> = [0xFE, 0xFF].into_iter()
+            .chain("héllo 世界\n".encode_utf16().flat_map(|unit| unit.to_be_bytes()))
+            .collect();
+        let (converted, charset) = to_utf8(content.clone(), None).unwrap();
+        let charset = charset.unwrap();
+        assert_eq!(Some(&content[..2]), byte_order_mark(&charset));
+        assert_eq!(&content[2..], from_utf8(&converted, &charset).unwrap());
+
+        assert!(from_utf8("日本".as_bytes(), "windows-1252").is_err());
+    }
+}
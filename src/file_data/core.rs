@@ -2,23 +2,92 @@ use std::error::Error;
 use std::future::Future;
 use std::path::PathBuf;
 use std::pin::Pin;
+use std::str::Utf8Error;
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+
+/// The future returned by [`FileDataExtractor::load_files`].
+pub type LoadFilesFuture<'life> =
+    Pin<Box<dyn Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send + 'life>>;
 
 #[derive(Debug, Clone)]
 pub struct FileData {
     pub(crate) is_text: Option<bool>,
     pub(crate) path_to_file: PathBuf,
+    /// The path of the file relative to the directory that was searched.
+    pub(crate) relative_path: PathBuf,
     pub(crate) size: Option<u64>,
+    /// The MIME type detected from the start of the file and its name.
+    pub(crate) mime_type: Option<String>,
+    /// The charset the content was converted to UTF-8 from, `None` if the
+    /// content was UTF-8 already.
+    pub(crate) charset: Option<String>,
+    /// The time the file was last modified, if the file system records it.
+    pub(crate) modified: Option<SystemTime>,
+    /// The unix permission bits of the file.
+    pub(crate) mode: Option<u32>,
+    /// The number of lines of a text file.
+    pub(crate) lines: Option<u64>,
+    /// The SHA-256 of the file as it is stored, in lower case hex. It is only
+    /// computed if the extractor was asked for checksums.
+    pub(crate) sha256: Option<String>,
     pub(crate) error: Option<String>
 }
 
+impl FileData {
+    /// Creates the data of a file that has not been read yet, the other
+    /// fields are filled in by the extractor
+    pub fn new(path_to_file: PathBuf, relative_path: PathBuf) -> Self {
+        Self {
+            is_text: None,
+            path_to_file,
+            relative_path,
+            size: None,
+            mime_type: None,
+            charset: None,
+            modified: None,
+            mode: None,
+            lines: None,
+            sha256: None,
+            error: None,
+        }
+    }
+
+    /// Returns the relative path with `/` as separator, which is how paths
+    /// are stored in archives regardless of platform.
+    pub fn archive_path(&self) -> String {
+        self.relative_path.components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// A file together with the bytes that were read from it. The content is
+/// loaded for text files, converted to UTF-8 if needed, and for binaries only
+/// if the extractor was asked to include them. It is empty for files with errors.
+#[derive(Debug)]
+pub struct LoadedFile {
+    pub(crate) data: FileData,
+    pub(crate) content: Vec<u8>,
+}
+
+impl LoadedFile {
+    /// Returns the content as text if it is valid UTF-8
+    pub fn text(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(&self.content)
+    }
+}
+
 pub trait FileDataExtractor {
 
-    /// Extracts metadata from a collection of files.
+    /// Reads a collection of files and sends each of them to `sender` as soon
+    /// as it has been read, ordered by path.
     ///
     /// For each file path provided, this function:
-    /// 1. Creates a `FileData` instance containing the file's metadata
+    /// 1. Reads the file once, keeping the bytes that were read
     /// 2. Determines the file size
-    /// 3. Analyzes whether the file is text or binary
+    /// 3. Analyzes whether the file is text or binary from the bytes it already has
     ///
     /// If any operation fails for a specific file, the error is captured in that file's
     /// `FileData.error` field rather than failing the entire operation. This allows
@@ -26,21 +95,23 @@ pub trait FileDataExtractor {
     ///
     /// # Arguments
     ///
-    /// * `file_paths` - A slice of `PathBuf` objects pointing to the files to analyze
+    /// * `file_paths` - A slice of `PathBuf` objects pointing to the files to load
+    /// * `sender` - The channel the loaded files are sent to, loading stops
+    ///   early if the receiving side is closed
     ///
     /// # Returns
     ///
-    /// A `Future` resolving to a `Result` containing a `Vec<FileData>` with metadata for each file
-    fn get_file_data<'life>(
+    /// A `Future` that resolves when all files have been sent
+    fn load_files<'life>(
         &'life self,
         file_paths: &'life [PathBuf],
-    ) -> Pin<Box<dyn Future<Output = Result<Vec<FileData>,
-                Box<dyn Error + Send + Sync>>> + Send + 'life>>;
+        sender: mpsc::Sender<LoadedFile>,
+    ) -> LoadFilesFuture<'life>;
 
 }
 
 fn filter(file_data_list: &[FileData], filter_fn: Box<dyn FnMut(&&FileData) -> bool>) -> Vec<FileData> {
-    file_data_list.into_iter()
+    file_data_list.iter()
         .filter(filter_fn)
         .cloned()
         .collect()
@@ -54,6 +125,10 @@ pub fn only_text_files(file_data_list: &[FileData]) -> Vec<FileData> {
     filter(file_data_list, Box::new(|fd| fd.is_text.is_some() && fd.is_text.unwrap()))
 }
 
+pub fn only_converted(file_data_list: &[FileData]) -> Vec<FileData> {
+    filter(file_data_list, Box::new(|fd| fd.charset.is_some()))
+}
+
 pub fn only_binaries(file_data_list: &[FileData]) -> Vec<FileData> {
     filter(file_data_list, Box::new(|fd| fd.is_text.is_some() && !fd.is_text.unwrap()))
 }
\ No newline at end of file
@@ -1,121 +1,489 @@
+use std::collections::VecDeque;
 use std::error::Error;
-use std::future::Future;
-use std::pin::Pin;
-use std::io::{Read, BufReader};
-use std::fs::File;
-use tokio::fs;
-use tokio::task;
-use std::path::PathBuf;
+use std::io::Read;
+use std::fs::{File, Metadata};
+use tokio::sync::mpsc;
+use tokio::task::{self, JoinHandle};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+use log::info;
+use sha2::{Digest, Sha256};
 
 // Importing from the crate instead of redefining
-use crate::file_data::core::{FileData, FileDataExtractor};
+use crate::file_data::charset::{decode_sample, is_text_in};
+use crate::file_data::core::{FileData, FileDataExtractor, LoadFilesFuture, LoadedFile};
+use crate::file_data::settings::LoadSettings;
+
+/// The number of bytes at the start of a file that text detection looks at.
+const SNIFF_LENGTH: u64 = 1024;
 
 pub struct FileDataExtractorImpl {
-    // We could add configuration options here if needed
+    /// The directory that relative paths are calculated from.
+    base_dir: PathBuf,
+    /// The maximum number of files that are read at the same time.
+    jobs: usize,
+    /// How the content of the files is classified and loaded.
+    settings: Arc<LoadSettings>,
 }
 
 impl FileDataExtractorImpl {
-    /// Creates a new instance of the ClaudeFileDataExtractorImpl
-    pub fn new() -> Self {
-        Self {}
+    /// Creates a new instance of the FileDataExtractorImpl that calculates
+    /// relative paths from the given base directory and reads at most `jobs`
+    /// files at the same time
+    pub fn new(base_dir: &Path, jobs: usize) -> Self {
+        Self {
+            base_dir: base_dir.to_path_buf(),
+            jobs: jobs.max(1),
+            settings: Arc::new(LoadSettings::default()),
+        }
     }
 
-    /// Checks if a file is likely a text file by examining its content
-    async fn is_text_file(path: &PathBuf) -> Result<bool, String> {
-        // Spawn a blocking task since file reading operations are blocking
-        let path_clone = path.clone();
-        let is_text = task::spawn_blocking(move || -> Result<bool, String> {
-            let file = File::open(&path_clone)
-                .map_err(|e| e.to_string())?;
-            let mut reader = BufReader::new(file);
-            let mut buffer = [0u8; 1024]; // Read first 1024 bytes
-
-            let bytes_read = reader.read(&mut buffer)
-                .map_err(|e| e.to_string())?;
-            if bytes_read == 0 {
-                return Ok(true); // Empty files are considered text files
+    /// Classifies and loads the files with the given settings instead of the
+    /// default ones
+    pub fn with_settings(mut self, settings: LoadSettings) -> Self {
+        self.settings = Arc::new(settings);
+        self
+    }
+
+    /// The number of jobs used when none is given, one per available CPU
+    pub fn default_jobs() -> usize {
+        std::thread::available_parallelism()
+            .map(|parallelism| parallelism.get())
+            .unwrap_or(4)
+    }
+
+    /// Returns the path relative to the base directory, or the path itself
+    /// if it is not inside the base directory
+    pub(crate) fn relative_path(base_dir: &Path, path: &Path) -> PathBuf {
+        path.strip_prefix(base_dir)
+            .map(|relative| relative.to_path_buf())
+            .unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    /// Checks if content is likely text by examining its first bytes. Content
+    /// with a UTF-8, UTF-16 or UTF-32 byte order mark is text. Otherwise NUL
+    /// bytes mean binary, and so does a high share of control characters and
+    /// bytes that are not part of a valid UTF-8 sequence. Valid multi-byte
+    /// sequences count as text, so that CJK or emoji heavy files are kept, and
+    /// so does content that decodes in a confidently detected legacy encoding,
+    /// such as Shift_JIS or GBK.
+    pub(crate) fn is_text_content(content: &[u8]) -> bool {
+        let sample = &content[..content.len().min(SNIFF_LENGTH as usize)];
+        if sample.is_empty() {
+            return true; // Empty files are considered text files
+        }
+
+        if Self::has_byte_order_mark(sample) {
+            return true;
+        }
+
+        if sample.contains(&0) {
+            return false;
+        }
+
+        // A sequence cut off by the end of the sample is not an error
+        let is_cut = sample.len() == SNIFF_LENGTH as usize;
+        let utf8_sample = if is_cut {
+            Self::trim_incomplete_sequence(sample)
+        } else {
+            sample
+        };
+
+        let mut non_text = 0;
+        for chunk in utf8_sample.utf8_chunks() {
+            non_text += chunk.invalid().len();
+            non_text += chunk.valid().chars().filter(|c| Self::is_binary_control(*c)).count();
+        }
+
+        // If more than 30% of the sample is non-text, consider it binary
+        if non_text <= utf8_sample.len() / 3 {
+            return true;
+        }
+
+        // unless it is text in a legacy encoding that is detected with
+        // confidence, which as real text has no control characters at all
+        let mut detector = EncodingDetector::new();
+        detector.feed(sample, !is_cut);
+        match detector.guess_assess(None, false) {
+            (encoding, true) if encoding != UTF_8 => decode_sample(sample, encoding)
+                .is_some_and(|text| !text.chars().any(Self::is_binary_control)),
+            _ => false,
+        }
+    }
+
+    /// Checks for the byte order marks of UTF-8, UTF-16 and UTF-32
+    fn has_byte_order_mark(sample: &[u8]) -> bool {
+        const BYTE_ORDER_MARKS: [&[u8]; 5] = [
+            &[0xEF, 0xBB, 0xBF],
+            &[0xFF, 0xFE, 0x00, 0x00],
+            &[0x00, 0x00, 0xFE, 0xFF],
+            &[0xFF, 0xFE],
+            &[0xFE, 0xFF],
+        ];
+        BYTE_ORDER_MARKS.iter().any(|mark| sample.starts_with(mark))
+    }
+
+    /// Control characters that do not occur in text, which excludes the
+    /// whitespace characters and escape, which is used for terminal colors
+    pub(crate) fn is_binary_control(c: char) -> bool {
+        c.is_control() && !matches!(c, '\t' | '\n' | '\u{0B}' | '\u{0C}' | '\r' | '\u{1B}')
+    }
+
+    /// Checks if content is likely text, in the pinned encoding if there is one
+    pub(crate) fn is_text(content: &[u8], encoding: Option<&'static Encoding>) -> bool {
+        match encoding {
+            Some(encoding) => is_text_in(&content[..content.len().min(SNIFF_LENGTH as usize)], encoding, Self::is_binary_control),
+            None => Self::is_text_content(content),
+        }
+    }
+
+    /// Counts the lines of text, where a line is ended by LF, CRLF or CR and
+    /// a last line without a line break counts as well
+    pub(crate) fn count_lines(text: &[u8]) -> u64 {
+        let line_breaks = text.iter()
+            .enumerate()
+            .filter(|(index, byte)| **byte == b'\n' || (**byte == b'\r' && text.get(index + 1) != Some(&b'\n')))
+            .count();
+        let unterminated = !text.is_empty() && !text.ends_with(b"\n") && !text.ends_with(b"\r");
+        (line_breaks + unterminated as usize) as u64
+    }
+
+    /// Returns the SHA-256 of some content in lower case hex
+    pub(crate) fn sha256_hex(content: &[u8]) -> String {
+        Sha256::digest(content).iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Returns the unix permission bits of a file
+    #[cfg(unix)]
+    fn file_mode(metadata: &Metadata) -> Option<u32> {
+        use std::os::unix::fs::PermissionsExt;
+        Some(metadata.permissions().mode() & 0o7777)
+    }
+
+    #[cfg(not(unix))]
+    fn file_mode(_metadata: &Metadata) -> Option<u32> {
+        None
+    }
+
+    /// Removes a UTF-8 sequence at the end that is missing its last bytes
+    fn trim_incomplete_sequence(sample: &[u8]) -> &[u8] {
+        for back in 1..=sample.len().min(3) {
+            let byte = sample[sample.len() - back];
+            if byte & 0xC0 == 0x80 {
+                continue; // continuation byte, keep looking for the lead byte
             }
+            let sequence_length = match byte {
+                0xF0.. => 4,
+                0xE0.. => 3,
+                0xC0.. => 2,
+                _ => 1,
+            };
+            if sequence_length > back {
+                return &sample[..sample.len() - back];
+            }
+            break;
+        }
+        sample
+    }
 
-            // Heuristic: Check for NULL bytes or high proportion of non-ASCII chars
-            let non_text_chars = buffer[..bytes_read]
-                .iter()
-                .filter(|&&b| b == 0 || b > 127)
-                .count();
+    /// Opens a file once, sniffs its first bytes and reads the rest of it
+    /// if it is a text file, which is then converted to UTF-8, or a binary
+    /// within the size limit
+    fn read_file(path: &Path, file_data: &mut FileData, settings: &LoadSettings) -> Result<Vec<u8>, String> {
+        let mut file = File::open(path)
+            .map_err(|e| e.to_string())?;
+        let metadata = file.metadata()
+            .map_err(|e| e.to_string())?;
+        let size = metadata.len();
+        file_data.modified = metadata.modified().ok();
+        file_data.mode = Self::file_mode(&metadata);
 
-            // If more than 30% of the first 1024 bytes are non-text, consider it binary
-            let threshold = bytes_read / 3;
-            Ok(non_text_chars <= threshold)
-        })
-            .await
+        // only the sniffed bytes are allocated until the file is known to be kept
+        let mut content = Vec::with_capacity(SNIFF_LENGTH as usize);
+        (&mut file).take(SNIFF_LENGTH).read_to_end(&mut content)
             .map_err(|e| e.to_string())?;
 
-        is_text
+        if !settings.classify_content(file_data, &content, size) {
+            return Ok(Vec::new());
+        }
+
+        content.reserve((size as usize).saturating_sub(content.len()));
+        file.read_to_end(&mut content)
+            .map_err(|e| e.to_string())?;
+
+        settings.convert_content(file_data, content)
+    }
+
+    /// Starts loading a single file into a LoadedFile, capturing errors in its FileData
+    fn spawn_load_file(path: PathBuf, relative_path: PathBuf, settings: Arc<LoadSettings>) -> JoinHandle<LoadedFile> {
+        // Spawn a blocking task since file reading operations are blocking
+        task::spawn_blocking(move || {
+            let mut file_data = FileData::new(path.clone(), relative_path);
+
+            match Self::read_file(&path, &mut file_data, &settings) {
+                Ok(content) => LoadedFile { data: file_data, content },
+                Err(e) => {
+                    // Store error as a string in the FileData struct
+                    file_data.error = Some(e);
+                    LoadedFile { data: file_data, content: Vec::new() }
+                }
+            }
+        })
+    }
+
+    /// Waits for a loading task and sends its result, returning false if the
+    /// receiver has been closed
+    async fn send_loaded_file(
+        handle: JoinHandle<LoadedFile>,
+        sender: &mpsc::Sender<LoadedFile>,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let loaded_file = handle.await
+            .map_err(|e| Box::<dyn Error + Send + Sync>::from(e.to_string()))?;
+        Ok(sender.send(loaded_file).await.is_ok())
     }
 }
 
 impl FileDataExtractor for FileDataExtractorImpl {
-    fn get_file_data<'life>(
+    fn load_files<'life>(
         &'life self,
         file_paths: &'life [PathBuf],
-    ) -> Pin<Box<dyn Future<Output = Result<Vec<FileData>, Box<dyn Error + Send + Sync>>> + Send + 'life>> {
+        sender: mpsc::Sender<LoadedFile>,
+    ) -> LoadFilesFuture<'life> {
         Box::pin(async move {
-            let mut tasks = Vec::with_capacity(file_paths.len());
-
-            // Create a task for each file
-            for path in file_paths {
-                let path_clone = path.clone();
-
-                // Process each file in parallel
-                let handle = tokio::spawn(async move {
-                    let mut file_data = FileData {
-                        is_text: None,
-                        path_to_file: path_clone.clone(),
-                        size: None,
-                        error: None
-                    };
-
-                    // Get file metadata
-                    match fs::metadata(&path_clone).await {
-                        Ok(metadata) => {
-                            file_data.size = Some(metadata.len());
-
-                            // Then check if it's a text file
-                            match Self::is_text_file(&path_clone).await {
-                                Ok(is_text) => file_data.is_text = Some(is_text),
-                                Err(e) => {
-                                    file_data.error = Some(e);
-                                },
-                            }
-                        },
-                        Err(e) => {
-                            // Store error as a string in the FileData struct
-                            file_data.error = Some(e.to_string());
-                        }
-                    }
-
-                    file_data
-                });
+            // Sort paths so that the order of the archive is deterministic
+            let mut sorted_paths: Vec<&PathBuf> = file_paths.iter().collect();
+            sorted_paths.sort();
 
-                tasks.push(handle);
-            }
+            // At most `jobs` files are read at once. Finished files are sent in
+            // path order, so a slow file holds back the ones after it rather
+            // than letting an unbounded number of loaded files pile up.
+            let mut in_flight: VecDeque<JoinHandle<LoadedFile>> = VecDeque::with_capacity(self.jobs);
 
-            // Collect results from all tasks
-            let mut results = Vec::with_capacity(file_paths.len());
-            for task in tasks {
-                match task.await {
-                    Ok(file_data) => {
-                        results.push(file_data);
-                    },
-                    Err(e) => {
-                        return Err(Box::<dyn Error + Send + Sync>::from(e.to_string()));
+            for path in sorted_paths {
+                if in_flight.len() == self.jobs {
+                    let handle = in_flight.pop_front().unwrap();
+                    if !Self::send_loaded_file(handle, &sender).await? {
+                        info!("load_files: receiver closed, stopping");
+                        return Ok(());
                     }
                 }
+
+                let relative_path = Self::relative_path(&self.base_dir, path);
+                in_flight.push_back(Self::spawn_load_file(path.clone(), relative_path, self.settings.clone()));
             }
 
-            // Sort results to match original order if necessary
-            results.sort_by(|a, b| a.path_to_file.cmp(&b.path_to_file));
+            while let Some(handle) = in_flight.pop_front() {
+                if !Self::send_loaded_file(handle, &sender).await? {
+                    info!("load_files: receiver closed, stopping");
+                    break;
+                }
+            }
 
-            Ok(results)
+            Ok(())
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_text_content_accepts_multilingual_utf8() {
+        let samples = [
+            "// 設定ファイルを読み込み、結果を返します。\nfn 読み込み() -> 結果 { todo!() }\n",
+            "# 中文文档\n\n这是一个用于测试的示例文件，包含简体中文。\n",
+            "Привет, мир! Это пример текста на русском языке.\n",
+            "مرحبا بالعالم، هذا نص تجريبي باللغة العربية.\n",
+            "🎉🚀✨ Release notes 🐛 fixes 🔥🔥🔥 and 👍 emoji everywhere 😀😃😄\n",
+            "Ελληνικά, हिन्दी, 한국어 and Ünïcödé in one line\n",
+        ];
+        for sample in samples {
+            assert!(FileDataExtractorImpl::is_text_content(sample.as_bytes()), "{}", sample);
+        }
+    }
+
+    #[test]
+    fn is_text_content_ignores_sequences_cut_off_by_the_sample() {
+        let text = "日本語".repeat(200);
+        let cut = &text.as_bytes()[..SNIFF_LENGTH as usize];
+        assert!(std::str::from_utf8(cut).is_err());
+        assert!(FileDataExtractorImpl::is_text_content(text.as_bytes()));
+    }
+
+    #[test]
+    fn is_text_content_accepts_byte_order_marks() {
+        let utf16: Vec<u8> = [0xFF, 0xFE].into_iter()
+            .chain("héllo wörld\n".encode_utf16().flat_map(|unit| unit.to_le_bytes()))
+            .collect();
+        assert!(FileDataExtractorImpl::is_text_content(&utf16));
+        assert!(FileDataExtractorImpl::is_text_content(&[0xEF, 0xBB, 0xBF, b'a']));
+        assert!(FileDataExtractorImpl::is_text_content(&[0x00, 0x00, 0xFE, 0xFF, 0x00, 0x00, 0x00, b'a']));
+    }
+
+    #[test]
+    fn is_text_content_rejects_binaries() {
+        // a PNG header contains NUL bytes
+        assert!(!FileDataExtractorImpl::is_text_content(b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR"));
+        // control characters and invalid UTF-8 without any NUL byte
+        let noise: Vec<u8> = (0..512u32).map(|i| (i * 97 % 251 + 1) as u8).collect();
+        assert!(!FileDataExtractorImpl::is_text_content(&noise));
+        // UTF-16 without a byte order mark has NUL bytes
+        let utf16: Vec<u8> = "plain".encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect();
+        assert!(!FileDataExtractorImpl::is_text_content(&utf16));
+    }
+
+    #[test]
+    fn is_text_content_accepts_legacy_multi_byte_text() {
+        let samples = [
+            (encoding_rs::SHIFT_JIS, "// 設定ファイルを読み込み、結果を返します。\n日本語のテキストファイルです。\n"),
+            (encoding_rs::GBK, "# 中文文档\n这是一个用于测试的示例文件，包含简体中文。\n"),
+            (encoding_rs::WINDOWS_1251, "Привет, мир! Это пример текста на русском языке.\n"),
+        ];
+        for (encoding, text) in samples {
+            let (content, _, _) = encoding.encode(text);
+            assert!(FileDataExtractorImpl::is_text_content(&content), "{}", encoding.name());
+        }
+    }
+
+    #[test]
+    fn is_text_content_accepts_latin1_text() {
+        // ISO-8859-1 encoded "Café crème brûlée" has a few invalid bytes
+        let latin1 = b"Caf\xe9 cr\xe8me br\xfbl\xe9e, na\xefve fa\xe7ade\n";
+        assert!(FileDataExtractorImpl::is_text_content(latin1));
+    }
+
+    #[tokio::test]
+    async fn load_files_sends_sorted_files_with_text_content_only() {
+        let dir = std::env::temp_dir().join(format!("filefuser-extractor-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("b.txt"), "text\n").unwrap();
+        std::fs::write(dir.join("a.bin"), [0u8; 2048]).unwrap();
+
+        let paths = vec![dir.join("b.txt"), dir.join("a.bin"), dir.join("missing.txt")];
+        let (sender, mut receiver) = mpsc::channel(paths.len());
+        FileDataExtractorImpl::new(&dir, 2).load_files(&paths, sender).await.unwrap();
+
+        let mut loaded = Vec::new();
+        while let Some(loaded_file) = receiver.recv().await {
+            loaded.push(loaded_file);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(3, loaded.len());
+        assert_eq!(PathBuf::from("a.bin"), loaded[0].data.relative_path);
+        assert_eq!((Some(false), Some(2048)), (loaded[0].data.is_text, loaded[0].data.size));
+        assert_eq!(Some("application/octet-stream".to_string()), loaded[0].data.mime_type);
+        assert!(loaded[0].content.is_empty());
+        assert_eq!(PathBuf::from("b.txt"), loaded[1].data.relative_path);
+        assert_eq!(b"text\n".to_vec(), loaded[1].content);
+        assert_eq!(Some("text/plain".to_string()), loaded[1].data.mime_type);
+        assert!(loaded[2].data.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn load_files_converts_detected_shift_jis_text() {
+        let dir = std::env::temp_dir().join(format!("filefuser-extractor-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let text = "// 設定ファイルを読み込み、結果を返します。\n日本語のテキストファイルです。\n".repeat(20);
+        let (content, _, _) = encoding_rs::SHIFT_JIS.encode(&text);
+        std::fs::write(dir.join("readme.txt"), &content).unwrap();
+
+        let paths = vec![dir.join("readme.txt")];
+        let (sender, mut receiver) = mpsc::channel(paths.len());
+        FileDataExtractorImpl::new(&dir, 1).load_files(&paths, sender).await.unwrap();
+        let loaded = receiver.recv().await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(None, loaded.data.error);
+        assert_eq!(Some(true), loaded.data.is_text);
+        assert_eq!(Some("Shift_JIS".to_string()), loaded.data.charset);
+        assert_eq!(text.into_bytes(), loaded.content);
+    }
+
+    #[tokio::test]
+    async fn load_files_loads_binaries_up_to_the_size_limit() {
+        let dir = std::env::temp_dir().join(format!("filefuser-extractor-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("small.bin"), [0u8; 2048]).unwrap();
+        std::fs::write(dir.join("large.bin"), [0u8; 4096]).unwrap();
+
+        let paths = vec![dir.join("small.bin"), dir.join("large.bin")];
+        let (sender, mut receiver) = mpsc::channel(paths.len());
+        FileDataExtractorImpl::new(&dir, 2).with_settings(LoadSettings::default().with_binary_size_limit(Some(2048))).load_files(&paths, sender).await.unwrap();
+
+        let large = receiver.recv().await.unwrap();
+        let small = receiver.recv().await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!((Some(false), Some(4096)), (large.data.is_text, large.data.size));
+        assert!(large.content.is_empty());
+        assert_eq!((Some(false), Some(2048)), (small.data.is_text, small.data.size));
+        assert_eq!(vec![0u8; 2048], small.content);
+    }
+
+    #[test]
+    fn count_lines_counts_every_line_break_style() {
+        assert_eq!(0, FileDataExtractorImpl::count_lines(b""));
+        assert_eq!(1, FileDataExtractorImpl::count_lines(b"no newline"));
+        assert_eq!(2, FileDataExtractorImpl::count_lines(b"a\nb\n"));
+        assert_eq!(3, FileDataExtractorImpl::count_lines(b"a\r\nb\rc"));
+    }
+
+    #[tokio::test]
+    async fn load_files_records_metadata_and_checksums() {
+        let dir = std::env::temp_dir().join(format!("filefuser-extractor-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "one\ntwo\n").unwrap();
+
+        let paths = vec![dir.join("a.txt")];
+        let (sender, mut receiver) = mpsc::channel(paths.len());
+        FileDataExtractorImpl::new(&dir, 1).with_settings(LoadSettings::default().with_checksums(true)).load_files(&paths, sender).await.unwrap();
+        let loaded = receiver.recv().await.unwrap();
+        let modified = std::fs::metadata(dir.join("a.txt")).unwrap().modified().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(Some(modified), loaded.data.modified);
+        assert_eq!(Some(2), loaded.data.lines);
+        assert_eq!(
+            Some("c3f9c8c283a2b1f2f1896f27a01cbe3cddc0c9d93f752e4639035a0f5b36f6e8".to_string()),
+            loaded.data.sha256
+        );
+        #[cfg(unix)]
+        assert!(loaded.data.mode.is_some());
+    }
+
+    #[tokio::test]
+    async fn load_files_keeps_path_order_with_limited_jobs() {
+        let dir = std::env::temp_dir().join(format!("filefuser-extractor-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut paths = Vec::new();
+        for index in (0..40).rev() {
+            let path = dir.join(format!("file_{:02}.txt", index));
+            std::fs::write(&path, "x".repeat(index * 1000)).unwrap();
+            paths.push(path);
+        }
+
+        let (sender, mut receiver) = mpsc::channel(1);
+        let extractor = FileDataExtractorImpl::new(&dir, 3);
+        let load = extractor.load_files(&paths, sender);
+        let receive = async {
+            let mut relative_paths = Vec::new();
+            while let Some(loaded_file) = receiver.recv().await {
+                relative_paths.push(loaded_file.data.relative_path);
+            }
+            relative_paths
+        };
+        let (load_result, relative_paths) = tokio::join!(load, receive);
+        load_result.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let expected: Vec<PathBuf> = (0..40).map(|index| PathBuf::from(format!("file_{:02}.txt", index))).collect();
+        assert_eq!(expected, relative_paths);
+    }
+}
@@ -0,0 +1,168 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use git2::{Repository, Tree};
+use log::info;
+use tokio::sync::mpsc;
+use tokio::task;
+
+use crate::file_data::core::{FileData, FileDataExtractor, LoadFilesFuture, LoadedFile};
+use crate::file_data::extractor_impl::FileDataExtractorImpl;
+use crate::file_data::settings::LoadSettings;
+use crate::git::{open_repository, rev_tree};
+
+/// Reads files from the git object database at a revision instead of from
+/// the work tree. The file paths are where the files would be in the work
+/// tree, as returned by [`crate::git::get_rev_files`].
+#[derive(Clone)]
+pub struct GitRevFileDataExtractor {
+    /// The directory that relative paths are calculated from.
+    base_dir: PathBuf,
+    /// The revision the files are read at.
+    rev: String,
+    /// How the content of the files is classified and loaded.
+    settings: LoadSettings,
+}
+
+impl GitRevFileDataExtractor {
+    /// Creates a new instance that reads files at `rev` from the repository
+    /// that contains `base_dir`
+    pub fn new(base_dir: &Path, rev: &str) -> Self {
+        Self {
+            base_dir: base_dir.to_path_buf(),
+            rev: rev.to_string(),
+            settings: LoadSettings::default(),
+        }
+    }
+
+    /// Classifies and loads the files with the given settings instead of the
+    /// default ones
+    pub fn with_settings(mut self, settings: LoadSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Reads a blob, keeping its content if it is text, which is then
+    /// converted to UTF-8, or a binary within the size limit
+    fn read_blob(
+        &self,
+        repo: &Repository,
+        tree: &Tree,
+        repo_path: &Path,
+        file_data: &mut FileData,
+    ) -> Result<Vec<u8>, String> {
+        let entry = tree.get_path(repo_path)
+            .map_err(|e| e.message().to_string())?;
+        let blob = entry.to_object(repo)
+            .and_then(|object| object.peel_to_blob())
+            .map_err(|e| e.message().to_string())?;
+        // git only records whether a file is executable
+        file_data.mode = Some(if entry.filemode() & 0o111 != 0 { 0o755 } else { 0o644 });
+
+        let content = blob.content();
+        if !self.settings.classify_content(file_data, content, content.len() as u64) {
+            return Ok(Vec::new());
+        }
+        self.settings.convert_content(file_data, content.to_vec())
+    }
+
+    /// Reads all files one after the other and sends them, stopping early if
+    /// the receiver is closed
+    fn load_blocking(&self, file_paths: &[PathBuf], sender: mpsc::Sender<LoadedFile>) -> Result<(), Box<dyn Error>> {
+        let (repo, work_dir) = open_repository(&self.base_dir)?;
+        let tree = rev_tree(&repo, &self.rev)?;
+
+        for path in file_paths {
+            let mut file_data = FileData::new(path.clone(), FileDataExtractorImpl::relative_path(&self.base_dir, path));
+
+            let result = match path.strip_prefix(&work_dir) {
+                Ok(repo_path) => self.read_blob(&repo, &tree, repo_path, &mut file_data),
+                Err(_) => Err(format!("not inside the repository at {}", work_dir.display())),
+            };
+
+            let loaded_file = match result {
+                Ok(content) => LoadedFile { data: file_data, content },
+                Err(e) => {
+                    file_data.error = Some(e);
+                    LoadedFile { data: file_data, content: Vec::new() }
+                }
+            };
+
+            if sender.blocking_send(loaded_file).is_err() {
+                info!("load_files: receiver closed, stopping");
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FileDataExtractor for GitRevFileDataExtractor {
+    fn load_files<'life>(
+        &'life self,
+        file_paths: &'life [PathBuf],
+        sender: mpsc::Sender<LoadedFile>,
+    ) -> LoadFilesFuture<'life> {
+        Box::pin(async move {
+            // Sort paths so that the order of the archive is deterministic
+            let mut sorted_paths = file_paths.to_vec();
+            sorted_paths.sort();
+
+            // git2 repositories can not be shared between threads, so the
+            // blobs are read one after the other on a single blocking task
+            let extractor = self.clone();
+            task::spawn_blocking(move || {
+                extractor.load_blocking(&sorted_paths, sender)
+                    .map_err(|e| e.to_string())
+            })
+                .await
+                .map_err(|e| Box::<dyn Error + Send + Sync>::from(e.to_string()))?
+                .map_err(Box::<dyn Error + Send + Sync>::from)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use git2::Signature;
+
+    #[tokio::test]
+    async fn load_files_reads_blobs_at_the_revision() {
+        let dir = std::env::temp_dir().join(format!("filefuser-git-rev-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let repo = Repository::init(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "committed\n").unwrap();
+        fs::write(dir.join("b.bin"), [0u8; 2048]).unwrap();
+
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("a.txt")).unwrap();
+        index.add_path(Path::new("b.bin")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[]).unwrap();
+
+        // the work tree has moved on and lost one of the files
+        fs::write(dir.join("a.txt"), "changed\n").unwrap();
+        fs::remove_file(dir.join("b.bin")).unwrap();
+
+        let paths = vec![dir.join("b.bin"), dir.join("a.txt"), dir.join("missing.txt")];
+        let (sender, mut receiver) = mpsc::channel(paths.len());
+        GitRevFileDataExtractor::new(&dir, "HEAD").load_files(&paths, sender).await.unwrap();
+
+        let mut loaded = Vec::new();
+        while let Some(loaded_file) = receiver.recv().await {
+            loaded.push(loaded_file);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(3, loaded.len());
+        assert_eq!(PathBuf::from("a.txt"), loaded[0].data.relative_path);
+        assert_eq!(b"committed\n".to_vec(), loaded[0].content);
+        assert_eq!((Some(false), Some(2048)), (loaded[1].data.is_text, loaded[1].data.size));
+        assert!(loaded[1].content.is_empty());
+        assert!(loaded[2].data.error.is_some());
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+/// Signatures at the start of binary files and the MIME types they identify.
+const MAGIC_NUMBERS: [(&[u8], &str); 18] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"BM", "image/bmp"),
+    (b"\x00\x00\x01\x00", "image/vnd.microsoft.icon"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"PK\x05\x06", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"BZh", "application/x-bzip2"),
+    (b"\xfd7zXZ\x00", "application/x-xz"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"\x7fELF", "application/x-elf"),
+    (b"MZ", "application/vnd.microsoft.portable-executable"),
+    (b"\x00asm", "application/wasm"),
+    (b"SQLite format 3\x00", "application/vnd.sqlite3"),
+    (b"\xca\xfe\xba\xbe", "application/java-vm"),
+];
+
+/// MIME types by lower case file extension, or by file name for files that
+/// are known by their name such as `Dockerfile`.
+const DEFAULT_TYPES: [(&str, &str); 60] = [
+    ("rs", "text/x-rust"),
+    ("py", "text/x-python"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("cjs", "text/javascript"),
+    ("jsx", "text/jsx"),
+    ("ts", "text/x-typescript"),
+    ("tsx", "text/tsx"),
+    ("go", "text/x-go"),
+    ("java", "text/x-java"),
+    ("kt", "text/x-kotlin"),
+    ("kts", "text/x-kotlin"),
+    ("scala", "text/x-scala"),
+    ("swift", "text/x-swift"),
+    ("c", "text/x-c"),
+    ("h", "text/x-c"),
+    ("cc", "text/x-c++"),
+    ("cpp", "text/x-c++"),
+    ("cxx", "text/x-c++"),
+    ("hpp", "text/x-c++"),
+    ("cs", "text/x-csharp"),
+    ("rb", "text/x-ruby"),
+    ("php", "text/x-php"),
+    ("lua", "text/x-lua"),
+    ("sh", "text/x-shellscript"),
+    ("bash", "text/x-shellscript"),
+    ("zsh", "text/x-shellscript"),
+    ("ps1", "text/x-powershell"),
+    ("sql", "application/sql"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("scss", "text/x-scss"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("json", "application/json"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("toml", "application/toml"),
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("txt", "text/plain"),
+    ("csv", "text/csv"),
+    ("ini", "text/plain"),
+    ("dockerfile", "text/x-dockerfile"),
+    ("makefile", "text/x-makefile"),
+    ("mk", "text/x-makefile"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("wasm", "application/wasm"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+];
+
+/// Interpreters named on a `#!` line and the MIME types of their scripts.
+const INTERPRETERS: [(&str, &str); 8] = [
+    ("sh", "text/x-shellscript"),
+    ("bash", "text/x-shellscript"),
+    ("zsh", "text/x-shellscript"),
+    ("python", "text/x-python"),
+    ("python3", "text/x-python"),
+    ("node", "text/javascript"),
+    ("ruby", "text/x-ruby"),
+    ("perl", "text/x-perl"),
+];
+
+/// Detects the MIME type of a file from the magic number at its start and
+/// from a table of file extensions that can be extended on the command line.
+#[derive(Debug, Clone)]
+pub struct MimeTypes {
+    by_extension: HashMap<String, String>,
+}
+
+impl MimeTypes {
+    /// Creates a table with the types that ship with filefuser
+    pub fn with_defaults() -> Self {
+        let by_extension = DEFAULT_TYPES.iter()
+            .map(|(extension, mime_type)| (extension.to_string(), mime_type.to_string()))
+            .collect();
+        Self { by_extension }
+    }
+
+    /// Adds `extension=type` mappings, replacing the default for an extension
+    pub fn with_mappings(mut self, mappings: &[String]) -> Result<Self, Box<dyn Error>> {
+        for mapping in mappings {
+            let (extension, mime_type) = mapping.split_once('=')
+                .filter(|(_, mime_type)| mime_type.contains('/'))
+                .ok_or_else(|| format!("MIME type mapping '{}' is not of the form extension=type/subtype", mapping))?;
+            let extension = extension.trim().trim_start_matches('.').to_ascii_lowercase();
+            self.by_extension.insert(extension, mime_type.trim().to_string());
+        }
+        Ok(self)
+    }
+
+    /// Returns the MIME type for a file given the bytes at its start. Magic
+    /// numbers of binary formats win over the extension, since they can not
+    /// be wrong, and files that match neither get a generic type.
+    pub fn detect(&self, path: &Path, sample: &[u8], is_text: bool) -> String {
+        if !is_text {
+            if let Some(mime_type) = Self::for_magic_number(sample) {
+                return mime_type.to_string();
+            }
+        }
+
+        if let Some(mime_type) = self.for_file_name(path) {
+            // a text type for a binary means the extension is misleading
+            if is_text || !mime_type.starts_with("text/") {
+                return mime_type.to_string();
+            }
+        }
+
+        match is_text {
+            true => Self::for_text(sample).unwrap_or("text/plain").to_string(),
+            false => "application/octet-stream".to_string(),
+        }
+    }
+
+    fn for_magic_number(sample: &[u8]) -> Option<&'static str> {
+        if sample.len() >= 12 && &sample[..4] == b"RIFF" && &sample[8..12] == b"WEBP" {
+            return Some("image/webp");
+        }
+        if sample.len() >= 262 && &sample[257..262] == b"ustar" {
+            return Some("application/x-tar");
+        }
+        MAGIC_NUMBERS.iter()
+            .find(|(magic, _)| sample.starts_with(magic))
+            .map(|(_, mime_type)| *mime_type)
+    }
+
+    fn for_file_name(&self, path: &Path) -> Option<&str> {
+        let file_name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+        let extension = path.extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+
+        self.by_extension.get(&file_name)
+            .or_else(|| extension.and_then(|extension| self.by_extension.get(&extension)))
+            .map(String::as_str)
+    }
+
+    /// Recognises scripts by their `#!` line and XML by its declaration
+    fn for_text(sample: &[u8]) -> Option<&'static str> {
+        if sample.starts_with(b"<?xml") {
+            return Some("application/xml");
+        }
+
+        let first_line = sample.strip_prefix(b"#!")?.split(|byte| *byte == b'\n').next()?;
+        let first_line = String::from_utf8_lossy(first_line);
+        let mut words = first_line.split_whitespace();
+        let mut interpreter = words.next()?.rsplit('/').next()?;
+        if interpreter == "env" {
+            interpreter = words.find(|word| !word.starts_with('-'))?;
+        }
+
+        INTERPRETERS.iter()
+            .find(|(name, _)| *name == interpreter)
+            .map(|(_, mime_type)| *mime_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_uses_the_extension_and_file_name() {
+        let types = MimeTypes::with_defaults();
+        assert_eq!("text/x-rust", types.detect(Path::new("src/main.rs"), b"fn main() {}", true));
+        assert_eq!("text/markdown", types.detect(Path::new("README.MD"), b"# Title", true));
+        assert_eq!("application/json", types.detect(Path::new("package.json"), b"{}", true));
+        assert_eq!("text/x-dockerfile", types.detect(Path::new("docker/Dockerfile"), b"FROM rust", true));
+        assert_eq!("text/plain", types.detect(Path::new("LICENSE"), b"MIT License", true));
+    }
+
+    #[test]
+    fn detect_prefers_magic_numbers_for_binaries() {
+        let types = MimeTypes::with_defaults();
+        assert_eq!("image/png", types.detect(Path::new("logo.jpg"), b"\x89PNG\r\n\x1a\n\x00\x00", false));
+        assert_eq!("application/x-elf", types.detect(Path::new("bin/tool"), b"\x7fELF\x02\x01", false));
+        assert_eq!("image/webp", types.detect(Path::new("a"), b"RIFF\x10\x00\x00\x00WEBPVP8 ", false));
+        assert_eq!("application/octet-stream", types.detect(Path::new("data.bin"), b"\x00\x01\x02", false));
+        assert_eq!("application/octet-stream", types.detect(Path::new("video.ts"), b"\x47\x00\x11", false));
+    }
+
+    #[test]
+    fn detect_recognises_scripts_and_xml_without_extension() {
+        let types = MimeTypes::with_defaults();
+        assert_eq!("text/x-python", types.detect(Path::new("bin/run"), b"#!/usr/bin/env -S python3 -u\nprint()", true));
+        assert_eq!("text/x-shellscript", types.detect(Path::new("configure"), b"#!/bin/sh\n", true));
+        assert_eq!("application/xml", types.detect(Path::new("feed"), b"<?xml version=\"1.0\"?>", true));
+    }
+
+    #[test]
+    fn with_mappings_overrides_the_defaults() {
+        let types = MimeTypes::with_defaults()
+            .with_mappings(&[".TS=video/mp2t".to_string(), "proto=text/x-protobuf".to_string()])
+            .unwrap();
+        assert_eq!("video/mp2t", types.detect(Path::new("clip.ts"), b"", true));
+        assert_eq!("text/x-protobuf", types.detect(Path::new("api.proto"), b"syntax", true));
+        assert!(MimeTypes::with_defaults().with_mappings(&["rs".to_string()]).is_err());
+    }
+}
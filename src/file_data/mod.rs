@@ -1,2 +1,6 @@
+pub(crate) mod charset;
 pub(crate) mod core;
-pub(crate) mod extractor_impl;
\ No newline at end of file
+pub(crate) mod extractor_impl;
+pub(crate) mod git_rev_extractor;
+pub(crate) mod mime;
+pub(crate) mod settings;
@@ -0,0 +1,89 @@
+use crate::file_data::charset::{to_utf8, EncodingRules};
+use crate::file_data::core::FileData;
+use crate::file_data::extractor_impl::FileDataExtractorImpl;
+use crate::file_data::mime::MimeTypes;
+
+/// How the content of files is classified and which of it is loaded, shared
+/// by the extractors that read from the work tree and from git.
+#[derive(Debug, Clone)]
+pub struct LoadSettings {
+    /// The encodings that are pinned instead of detected.
+    encodings: EncodingRules,
+    /// The table MIME types are detected with.
+    mime_types: MimeTypes,
+    /// The size up to which the content of binaries is loaded, binaries are
+    /// never loaded if this is `None`.
+    binary_size_limit: Option<u64>,
+    /// Whether the SHA-256 of every loaded file is computed.
+    checksums: bool,
+}
+
+impl Default for LoadSettings {
+    fn default() -> Self {
+        Self {
+            encodings: EncodingRules::empty(),
+            mime_types: MimeTypes::with_defaults(),
+            binary_size_limit: None,
+            checksums: false,
+        }
+    }
+}
+
+impl LoadSettings {
+    /// Pins the encoding of the files matching the rules instead of detecting it
+    pub fn with_encodings(mut self, encodings: EncodingRules) -> Self {
+        self.encodings = encodings;
+        self
+    }
+
+    /// Detects MIME types with the given table instead of the default one
+    pub fn with_mime_types(mut self, mime_types: MimeTypes) -> Self {
+        self.mime_types = mime_types;
+        self
+    }
+
+    /// Loads the content of binaries that are at most `size_limit` bytes,
+    /// or of no binaries at all if it is `None`
+    pub fn with_binary_size_limit(mut self, size_limit: Option<u64>) -> Self {
+        self.binary_size_limit = size_limit;
+        self
+    }
+
+    /// Computes the SHA-256 of every file whose content is loaded
+    pub fn with_checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// Records the size, text status and MIME type of a file from the first
+    /// bytes of its content, and returns whether the rest of it is loaded
+    pub(crate) fn classify_content(&self, file_data: &mut FileData, sample: &[u8], size: u64) -> bool {
+        let encoding = self.encodings.encoding_for(&file_data.relative_path);
+        let is_text = FileDataExtractorImpl::is_text(sample, encoding);
+        file_data.is_text = Some(is_text);
+        file_data.size = Some(size);
+        file_data.mime_type = Some(self.mime_types.detect(&file_data.relative_path, sample, is_text));
+
+        is_text || self.binary_size_limit.is_some_and(|limit| size <= limit)
+    }
+
+    /// Records what is known once the whole content of a classified file is
+    /// loaded, and converts text to UTF-8
+    pub(crate) fn convert_content(&self, file_data: &mut FileData, content: Vec<u8>) -> Result<Vec<u8>, String> {
+        // the size is taken from what was read in case the file changed
+        file_data.size = Some(content.len() as u64);
+        if self.checksums {
+            file_data.sha256 = Some(FileDataExtractorImpl::sha256_hex(&content));
+        }
+
+        if file_data.is_text != Some(true) {
+            return Ok(content);
+        }
+
+        let encoding = self.encodings.encoding_for(&file_data.relative_path);
+        let (content, charset) = to_utf8(content, encoding)?;
+        file_data.charset = charset;
+        file_data.lines = Some(FileDataExtractorImpl::count_lines(&content));
+        Ok(content)
+    }
+}
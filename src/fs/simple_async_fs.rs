@@ -12,13 +12,6 @@ use crate::fs::async_fs::{AsyncFS, FileContent, FileEntry, FileMetadata};
 /// A new "SimpleV3AsyncFS" implementation that does not depend on `glob`.
 pub struct SimpleAsyncFS;
 
-impl SimpleAsyncFS {
-    /// Creates a new instance of SimpleV3AsyncFS.
-    pub fn new() -> Self {
-        SimpleAsyncFS
-    }
-}
-
 impl AsyncFS for SimpleAsyncFS {
     fn to_absolute_path<'a>(
         &'a self,
@@ -33,10 +26,10 @@ impl AsyncFS for SimpleAsyncFS {
                 let abs_str = canonical
                     .to_str()
                     .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid UTF-8"))?;
-                Ok(abs_str.to_string())
+                Ok::<String, io::Error>(abs_str.to_string())
             })
                 .await
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("JoinError: {e}")))??;
+                .map_err(|e| io::Error::other(format!("JoinError: {e}")))??;
 
             Ok(absolute)
         })
@@ -118,7 +111,7 @@ impl AsyncFS for SimpleAsyncFS {
                     }
                 })
                     .await
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("JoinError: {e}")))??;
+                    .map_err(|e| io::Error::other(format!("JoinError: {e}")))??;
 
                 for path in entries {
                     let metadata = match tokio_fs::metadata(&path).await {
@@ -0,0 +1,397 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use git2::{Commit, Delta, DiffOptions, ObjectType, Repository, StatusOptions, Tree, TreeWalkMode, TreeWalkResult};
+use log::info;
+
+/// The file mode git uses for submodules, which are directories in the work tree.
+const GITLINK_MODE: u32 = 0o160000;
+/// The file mode git uses for symbolic links.
+const SYMLINK_MODE: i32 = 0o120000;
+
+/// Selects files from the git repository that contains the search directory
+/// instead of scanning the directory.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GitSelection {
+    /// Files in the git index.
+    Tracked,
+    /// Files that differ from the given revision, including changes that are
+    /// only in the work tree and untracked files that are not ignored.
+    Changed(String),
+    /// Files with changes that are staged for the next commit.
+    Staged,
+}
+
+/// Describes the state of the repository an archive was created from, so
+/// that the receiver can tell which revision the files came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepoContext {
+    /// The current branch when the files were read from the work tree.
+    pub(crate) branch: Option<String>,
+    /// The revision the files were read at instead of the work tree.
+    pub(crate) revision: Option<String>,
+    /// The hash and subject of the commit.
+    pub(crate) commit: Option<(String, String)>,
+    /// Whether the work tree has uncommitted changes, unknown when the files
+    /// were read at a revision.
+    pub(crate) dirty: Option<bool>,
+    /// Short hashes and subjects of the commits before the commit.
+    pub(crate) recent_commits: Vec<(String, String)>,
+}
+
+impl RepoContext {
+    /// Describes the context as plain text lines
+    pub fn describe(&self) -> String {
+        let mut description = String::from("Repository context:\n");
+
+        if let Some(branch) = &self.branch {
+            description.push_str(&format!("Branch: {}\n", branch));
+        }
+        if let Some(revision) = &self.revision {
+            description.push_str(&format!("Revision: {}\n", revision));
+        }
+        match &self.commit {
+            Some((hash, subject)) => description.push_str(&format!("Commit: {} {}\n", hash, subject)),
+            None => description.push_str("Commit: none\n"),
+        }
+        match self.dirty {
+            Some(true) => description.push_str("Working tree: uncommitted changes\n"),
+            Some(false) => description.push_str("Working tree: clean\n"),
+            None => {}
+        }
+        if !self.recent_commits.is_empty() {
+            description.push_str("Recent commits:\n");
+            for (hash, subject) in &self.recent_commits {
+                description.push_str(&format!("  {} {}\n", hash, subject));
+            }
+        }
+
+        description
+    }
+}
+
+/// Reads the context of the repository that contains `dir`, at `rev` or at
+/// HEAD with the work tree status, listing up to `count` earlier commits
+pub fn get_repo_context(dir: &Path, rev: Option<&str>, count: usize) -> Result<RepoContext, Box<dyn Error>> {
+    let (repo, work_dir) = open_repository(dir)?;
+    info!("get_repo_context: reading context of {:?}", work_dir);
+
+    let (branch, revision, commit, dirty) = match rev {
+        Some(rev) => {
+            let commit = repo.revparse_single(rev)
+                .and_then(|object| object.peel_to_commit())
+                .map_err(|e| format!("can not resolve git revision '{}': {}", rev, e.message()))?;
+            (None, Some(rev.to_string()), Some(commit), None)
+        }
+        None => {
+            let head = repo.head().ok();
+            let branch = match &head {
+                Some(head) if head.is_branch() => head.shorthand().map(str::to_string),
+                Some(_) => Some("detached HEAD".to_string()),
+                // an unborn branch still has a name even without commits
+                None => repo.find_reference("HEAD").ok()
+                    .and_then(|reference| reference.symbolic_target().map(str::to_string))
+                    .map(|target| target.trim_start_matches("refs/heads/").to_string()),
+            };
+            let commit = head.and_then(|head| head.peel_to_commit().ok());
+            (branch, None, commit, Some(is_dirty(&repo)?))
+        }
+    };
+
+    let mut recent_commits = Vec::new();
+    if let Some(commit) = &commit {
+        let mut walk = repo.revwalk()?;
+        walk.push(commit.id())?;
+        for oid in walk.skip(1).take(count) {
+            let recent = repo.find_commit(oid?)?;
+            recent_commits.push((short_hash(&recent), subject(&recent)));
+        }
+    }
+
+    Ok(RepoContext {
+        branch,
+        revision,
+        commit: commit.map(|commit| (commit.id().to_string(), subject(&commit))),
+        dirty,
+        recent_commits,
+    })
+}
+
+/// Checks if tracked files have changes that are not committed
+fn is_dirty(repo: &Repository) -> Result<bool, Box<dyn Error>> {
+    let mut options = StatusOptions::new();
+    options.include_untracked(false).include_ignored(false);
+    Ok(!repo.statuses(Some(&mut options))?.is_empty())
+}
+
+fn subject(commit: &Commit) -> String {
+    commit.summary().unwrap_or_default().to_string()
+}
+
+fn short_hash(commit: &Commit) -> String {
+    commit.id().to_string()[..7].to_string()
+}
+
+/// Returns the files below `dir` that are selected from the repository that
+/// contains `dir`. Files that were deleted are left out since there is
+/// nothing to archive for them.
+pub fn get_git_files(dir: &Path, selection: &GitSelection) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let (repo, work_dir) = open_repository(dir)?;
+
+    info!("get_git_files: selecting {:?} in {:?}", selection, work_dir);
+
+    let repo_paths = match selection {
+        GitSelection::Tracked => tracked_paths(&repo)?,
+        GitSelection::Changed(rev) => changed_paths(&repo, rev)?,
+        GitSelection::Staged => staged_paths(&repo)?,
+    };
+
+    let mut files: Vec<PathBuf> = repo_paths.iter()
+        .map(|path| work_dir.join(path))
+        .filter(|path| path.starts_with(dir) && path.is_file())
+        .collect();
+    files.sort();
+
+    info!("get_git_files: selected {:?} files", files.len());
+
+    Ok(files)
+}
+
+/// Returns the files below `dir` in the tree of `rev`. The paths are where the
+/// files would be in the work tree, whether or not they exist there.
+pub fn get_rev_files(dir: &Path, rev: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let (repo, work_dir) = open_repository(dir)?;
+    let tree = rev_tree(&repo, rev)?;
+
+    info!("get_rev_files: listing files at {:?} in {:?}", rev, work_dir);
+
+    let mut files = Vec::new();
+    let mut error = None;
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        // symlinks and submodules have no content of their own to archive
+        if entry.kind() != Some(ObjectType::Blob) || entry.filemode() == SYMLINK_MODE {
+            return TreeWalkResult::Ok;
+        }
+        match entry.name() {
+            Some(name) => {
+                let path = work_dir.join(root).join(name);
+                if path.starts_with(dir) {
+                    files.push(path);
+                }
+                TreeWalkResult::Ok
+            }
+            None => {
+                error = Some(format!("git path '{}{}' is not valid UTF-8", root, String::from_utf8_lossy(entry.name_bytes())));
+                TreeWalkResult::Abort
+            }
+        }
+    })?;
+    if let Some(error) = error {
+        return Err(error.into());
+    }
+
+    info!("get_rev_files: listed {:?} files", files.len());
+
+    Ok(files)
+}
+
+/// Opens the repository that contains `dir` and returns it together with its
+/// canonical work tree directory
+pub fn open_repository(dir: &Path) -> Result<(Repository, PathBuf), Box<dyn Error>> {
+    let repo = Repository::discover(dir)
+        .map_err(|e| format!("'{}' is not inside a git repository: {}", dir.display(), e.message()))?;
+    let work_dir = repo.workdir()
+        .ok_or("bare git repositories have no files to archive")?
+        .canonicalize()?;
+    Ok((repo, work_dir))
+}
+
+/// Resolves a revision such as a tag, branch or commit hash to its tree
+pub fn rev_tree<'repo>(repo: &'repo Repository, rev: &str) -> Result<Tree<'repo>, Box<dyn Error>> {
+    Ok(repo.revparse_single(rev)
+        .and_then(|object| object.peel_to_tree())
+        .map_err(|e| format!("can not resolve git revision '{}': {}", rev, e.message()))?)
+}
+
+/// Returns the paths of all files in the index, relative to the work tree
+fn tracked_paths(repo: &Repository) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let index = repo.index()?;
+    index.iter()
+        .filter(|entry| entry.mode != GITLINK_MODE)
+        .map(|entry| String::from_utf8(entry.path)
+            .map(PathBuf::from)
+            .map_err(|e| format!("git path '{}' is not valid UTF-8", String::from_utf8_lossy(e.as_bytes())).into()))
+        .collect()
+}
+
+/// Returns the paths of files that differ between `rev` and the work tree
+fn changed_paths(repo: &Repository, rev: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let tree = rev_tree(repo, rev)?;
+
+    let mut options = DiffOptions::new();
+    options.include_untracked(true).recurse_untracked_dirs(true);
+
+    let diff = repo.diff_tree_to_workdir_with_index(Some(&tree), Some(&mut options))?;
+    Ok(new_file_paths(&diff))
+}
+
+/// Returns the paths of files that differ between HEAD and the index
+fn staged_paths(repo: &Repository) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    // in a repository without commits everything in the index is staged
+    let head_tree: Option<Tree> = match repo.head() {
+        Ok(head) => Some(head.peel_to_tree()?),
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let diff = repo.diff_tree_to_index(head_tree.as_ref(), None, None)?;
+    Ok(new_file_paths(&diff))
+}
+
+/// Returns the paths on the new side of a diff, skipping deleted files
+fn new_file_paths(diff: &git2::Diff) -> Vec<PathBuf> {
+    diff.deltas()
+        .filter(|delta| delta.status() != Delta::Deleted)
+        .filter_map(|delta| delta.new_file().path().map(Path::to_path_buf))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use git2::{IndexAddOption, Signature};
+
+    fn create_repo() -> (PathBuf, Repository) {
+        let dir = std::env::temp_dir().join(format!("filefuser-git-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let repo = Repository::init(&dir).unwrap();
+        (dir, repo)
+    }
+
+    fn commit_all(repo: &Repository, message: &str) {
+        let mut index = repo.index().unwrap();
+        index.add_all(["*"], IndexAddOption::DEFAULT, None).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let parents: Vec<git2::Commit> = repo.head().ok()
+            .map(|head| vec![head.peel_to_commit().unwrap()])
+            .unwrap_or_default();
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents).unwrap();
+    }
+
+    fn stage(repo: &Repository, path: &str) {
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(path)).unwrap();
+        index.write().unwrap();
+    }
+
+    fn relative(dir: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files.iter()
+            .map(|path| path.strip_prefix(dir).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect()
+    }
+
+    #[test]
+    fn selections_follow_the_repository_state() {
+        let (dir, repo) = create_repo();
+        fs::write(dir.join("README.md"), "readme").unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.join("src/lib.rs"), "").unwrap();
+        commit_all(&repo, "initial");
+
+        fs::write(dir.join("src/main.rs"), "fn main() { run() }").unwrap();
+        fs::write(dir.join("src/staged.rs"), "").unwrap();
+        stage(&repo, "src/staged.rs");
+        fs::write(dir.join("src/untracked.rs"), "").unwrap();
+        fs::remove_file(dir.join("src/lib.rs")).unwrap();
+
+        let tracked = get_git_files(&dir, &GitSelection::Tracked).unwrap();
+        let changed = get_git_files(&dir, &GitSelection::Changed("HEAD".to_string())).unwrap();
+        let staged = get_git_files(&dir, &GitSelection::Staged).unwrap();
+        let changed_in_src = get_git_files(&dir.join("src"), &GitSelection::Changed("HEAD".to_string())).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vec!["README.md", "src/main.rs", "src/staged.rs"], relative(&dir, tracked));
+        assert_eq!(vec!["src/main.rs", "src/staged.rs", "src/untracked.rs"], relative(&dir, changed));
+        assert_eq!(vec!["src/staged.rs"], relative(&dir, staged));
+        assert_eq!(vec!["src/main.rs", "src/staged.rs", "src/untracked.rs"], relative(&dir, changed_in_src));
+    }
+
+    #[test]
+    fn staged_works_before_the_first_commit() {
+        let (dir, repo) = create_repo();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("b.txt"), "b").unwrap();
+        stage(&repo, "a.txt");
+
+        let staged = get_git_files(&dir, &GitSelection::Staged).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vec!["a.txt"], relative(&dir, staged));
+    }
+
+    #[test]
+    fn rev_files_come_from_the_tree_of_the_revision() {
+        let (dir, repo) = create_repo();
+        fs::write(dir.join("README.md"), "readme").unwrap();
+        fs::write(dir.join("src/old.rs"), "").unwrap();
+        commit_all(&repo, "initial");
+        repo.tag_lightweight("v1", &repo.head().unwrap().peel(ObjectType::Commit).unwrap(), false).unwrap();
+
+        fs::remove_file(dir.join("src/old.rs")).unwrap();
+        fs::write(dir.join("src/new.rs"), "").unwrap();
+        let mut index = repo.index().unwrap();
+        index.remove_path(Path::new("src/old.rs")).unwrap();
+        index.write().unwrap();
+        commit_all(&repo, "second");
+
+        let at_tag = get_rev_files(&dir, "v1").unwrap();
+        let in_src = get_rev_files(&dir.join("src"), "HEAD").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vec!["README.md", "src/old.rs"], relative(&dir, at_tag));
+        assert_eq!(vec!["src/new.rs"], relative(&dir, in_src));
+    }
+
+    #[test]
+    fn repo_context_describes_head_and_recent_commits() {
+        let (dir, repo) = create_repo();
+        for (index, subject) in ["first", "second", "third"].iter().enumerate() {
+            fs::write(dir.join("a.txt"), index.to_string()).unwrap();
+            commit_all(&repo, subject);
+        }
+        let head = repo.head().unwrap().peel_to_commit().unwrap().id().to_string();
+        let branch = repo.head().unwrap().shorthand().unwrap().to_string();
+
+        let clean = get_repo_context(&dir, None, 1).unwrap();
+        fs::write(dir.join("a.txt"), "changed").unwrap();
+        let dirty = get_repo_context(&dir, None, 5).unwrap();
+        let at_rev = get_repo_context(&dir, Some("HEAD~1"), 5).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(Some(branch.clone()), clean.branch);
+        assert_eq!(Some((head.clone(), "third".to_string())), clean.commit);
+        assert_eq!(Some(false), clean.dirty);
+        assert_eq!(vec!["second"], clean.recent_commits.iter().map(|(_, subject)| subject.as_str()).collect::<Vec<_>>());
+        assert_eq!(Some(true), dirty.dirty);
+        assert_eq!(2, dirty.recent_commits.len());
+        assert_eq!((None, Some("HEAD~1".to_string()), None), (at_rev.branch, at_rev.revision, at_rev.dirty));
+        assert_eq!("second", at_rev.commit.unwrap().1);
+        assert!(clean.describe().starts_with(&format!("Repository context:\nBranch: {}\nCommit: {} third\nWorking tree: clean\nRecent commits:\n  ", branch, head)));
+    }
+
+    #[test]
+    fn unknown_revision_is_an_error() {
+        let (dir, repo) = create_repo();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        commit_all(&repo, "initial");
+
+        let result = get_git_files(&dir, &GitSelection::Changed("no-such-branch".to_string()));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.unwrap_err().to_string().contains("no-such-branch"));
+    }
+}
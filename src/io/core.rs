@@ -1,19 +1,81 @@
 use std::error::Error;
 use std::future::Future;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use tokio::sync::mpsc;
+use crate::file_data::core::LoadedFile;
+
+/// The future returned by [`Archiver::archive`].
+pub type ArchiveFuture<'life> =
+    Pin<Box<dyn Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send + 'life>>;
+
+/// The future returned by [`Unarchiver::unarchive`].
+pub type UnarchiveFuture<'life> =
+    Pin<Box<dyn Future<Output = Result<UnarchiveReport, Box<dyn Error + Send + Sync>>> + Send + 'life>>;
+
+/// Settings for archivers that are given on the command line. Archivers
+/// ignore the settings that do not apply to their format.
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveOptions {
+    /// A description of where the files came from, such as the git
+    /// repository context, which is added in front of the files.
+    pub(crate) context: Option<String>,
+    /// Whether the archive must only use encodings that any mail tooling
+    /// accepts, at the cost of encoding more of its content.
+    pub(crate) strict_rfc: bool,
+    /// Whether the size, modification time, permissions, checksum and line
+    /// count of every file are added to the archive where the format allows.
+    pub(crate) metadata: bool,
+    /// The sender, recipient and subject of the archive, formats without
+    /// headers ignore them and the others use a default for `None`.
+    pub(crate) from: Option<String>,
+    pub(crate) to: Option<String>,
+    pub(crate) subject: Option<String>,
+    /// Extra headers added to the archive as name and value.
+    pub(crate) headers: Vec<(String, String)>,
+    /// Text that replaces the standard introduction and the context.
+    pub(crate) introduction: Option<String>,
+}
 
 pub trait Archiver {
 
-    /// Creates an archive file from a list of PathBuf that contains information
-    /// about the files to be archived. The archive can be of different formats
-    /// but its important that the contents and info about the files are captured
-    /// in the archive.
+    /// Creates an archive file from the loaded files received on `files`, which
+    /// carry both information about the files and their content. The archive can
+    /// be of different formats but its important that the contents and info about
+    /// the files are captured in the archive. Files are written as they arrive so
+    /// that the whole archive never has to be held in memory.
     fn archive<'life>(
         &'life self,
-        file_path: &'life PathBuf,
-        file_paths: &'life [PathBuf],
-    ) -> Pin<Box<dyn Future<Output = Result<(),
-            Box<dyn Error + Send + Sync>>> + Send + 'life>>;
+        file_path: &'life Path,
+        files: mpsc::Receiver<LoadedFile>,
+    ) -> ArchiveFuture<'life>;
+
+}
+
+/// A part of an archive that was not extracted and the reason why.
+#[derive(Debug, Clone)]
+pub struct SkippedPart {
+    pub(crate) name: String,
+    pub(crate) reason: String,
+}
+
+/// The outcome of extracting an archive.
+#[derive(Debug, Default)]
+pub struct UnarchiveReport {
+    pub(crate) extracted: Vec<PathBuf>,
+    pub(crate) skipped: Vec<SkippedPart>,
+}
+
+pub trait Unarchiver {
+
+    /// Reads the archive at `archive_path` and recreates the files it contains
+    /// under `target_dir`. Parts that cannot be extracted safely are not written
+    /// but reported in the returned `UnarchiveReport` so that a single bad part
+    /// does not stop the rest of the archive from being restored.
+    fn unarchive<'life>(
+        &'life self,
+        archive_path: &'life Path,
+        target_dir: &'life Path,
+    ) -> UnarchiveFuture<'life>;
 
-}
\ No newline at end of file
+}
@@ -1,160 +1,421 @@
+use std::borrow::Cow;
 use std::error::Error;
-use std::future::Future;
-use std::path::PathBuf;
-use std::pin::Pin;
+use std::path::Path;
 use log::{info, warn, error};
-use tokio::fs::{self, File};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use uuid::Uuid;
-use chrono::Utc;
-use crate::io::core::Archiver;
+use chrono::{DateTime, SecondsFormat, Utc};
+use crate::file_data::core::{FileData, LoadedFile};
+use crate::io::core::{ArchiveFuture, ArchiveOptions, Archiver};
+use crate::io::files::create_output_file;
+use crate::io::line_endings::LineEndings;
+use crate::io::transfer_encoding::{encode_quoted_printable, TransferEncoding};
+
+/// The exact length of the file in bytes.
+pub(crate) const LENGTH_HEADER: &str = "X-Filefuser-Length";
+/// The line breaks of a text file, which are CRLF in the part.
+pub(crate) const LINE_ENDINGS_HEADER: &str = "X-Filefuser-Line-Endings";
+/// Whether a text file ends with a line break.
+pub(crate) const FINAL_NEWLINE_HEADER: &str = "X-Filefuser-Final-Newline";
+/// The charset a text file was converted to UTF-8 from.
+pub(crate) const CHARSET_HEADER: &str = "X-Filefuser-Charset";
+/// The size of the file on disk when it was read.
+pub(crate) const SIZE_HEADER: &str = "X-Filefuser-Size";
+/// The modification time of the file in RFC 3339 format.
+pub(crate) const MTIME_HEADER: &str = "X-Filefuser-Mtime";
+/// The unix permission bits of the file in octal.
+pub(crate) const MODE_HEADER: &str = "X-Filefuser-Mode";
+/// The SHA-256 of the file as it is stored, in hex.
+pub(crate) const SHA256_HEADER: &str = "X-Filefuser-Sha256";
+/// The number of lines of a text file.
+pub(crate) const LINES_HEADER: &str = "X-Filefuser-Lines";
+
+/// The size of the pieces text is written in after its line breaks are converted.
+const CANONICAL_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The headers of the message when none are configured.
+const DEFAULT_FROM: &str = "EmlArchiver <archiver@example.com>";
+const DEFAULT_TO: &str = "User <user@example.com>";
+const DEFAULT_SUBJECT: &str = "Archived Files";
+
+/// The most bytes of UTF-8 in one RFC 2047 encoded word. The word is then
+/// 64 characters, which leaves room for the header name on the first line.
+const ENCODED_WORD_BYTES: usize = 39;
+
+/// The headers of a file part that describe the original file.
+#[derive(Debug, Default)]
+struct PartHeaders {
+    /// Parameters added to the Content-Disposition header, such as `size`.
+    disposition_params: Vec<(&'static str, String)>,
+    /// Headers written after the standard ones, such as `X-Filefuser-Length`.
+    file_headers: Vec<(&'static str, String)>,
+}
 
-pub struct EmlArchiver;
+pub struct EmlArchiver {
+    /// Text added to the introduction part after the fixed sentence.
+    context: Option<String>,
+    /// Text that replaces the fixed sentence and the context.
+    introduction: Option<String>,
+    from: String,
+    to: String,
+    subject: String,
+    /// Headers added to the message after the standard ones.
+    headers: Vec<(String, String)>,
+    /// Whether text is encoded so that it is accepted by any mail tooling.
+    strict_rfc: bool,
+    /// Whether the metadata of every file is added to its part.
+    metadata: bool,
+}
 
 impl EmlArchiver {
-    /// Creates a new EmlArchiver instance
-    pub fn new() -> Self {
+    /// Creates a new EmlArchiver instance with the given options
+    pub fn with_options(options: &ArchiveOptions) -> Self {
         info!("Creating new EmlArchiver");
-        Self {}
+        Self {
+            context: options.context.clone(),
+            introduction: options.introduction.clone(),
+            from: options.from.clone().unwrap_or_else(|| DEFAULT_FROM.to_string()),
+            to: options.to.clone().unwrap_or_else(|| DEFAULT_TO.to_string()),
+            subject: options.subject.clone().unwrap_or_else(|| DEFAULT_SUBJECT.to_string()),
+            headers: options.headers.clone(),
+            strict_rfc: options.strict_rfc,
+            metadata: options.metadata,
+        }
     }
 
-    /// Generates a MIME boundary for multipart messages
+    /// Generates a MIME boundary for multipart messages. The value does not
+    /// include the `--` that starts every delimiter line.
     fn generate_boundary() -> String {
-        format!("--boundary_{}", Uuid::new_v4())
+        format!("boundary_{}", Uuid::new_v4())
+    }
+
+    /// Checks if the content has a line starting with the delimiter for the
+    /// boundary, which a MIME parser would take as the end of the part
+    fn contains_delimiter(content: &str, boundary: &str) -> bool {
+        let delimiter = format!("--{}", boundary);
+        content.starts_with(&delimiter)
+            || content.contains(&format!("\n{}", delimiter))
+            || content.contains(&format!("\r{}", delimiter))
+    }
+
+    /// Encodes content as base64 with lines of 76 characters as required by RFC 2045
+    fn encode_base64(content: &[u8]) -> String {
+        let encoded = BASE64.encode(content);
+        let mut wrapped = String::with_capacity(encoded.len() + encoded.len() / 76 * 2 + 2);
+        for line in encoded.as_bytes().chunks(76) {
+            wrapped.push_str(std::str::from_utf8(line).unwrap());
+            wrapped.push_str("\r\n");
+        }
+        wrapped
     }
 
     /// Creates EML headers with the given boundary
-    fn create_eml_headers(boundary: &str) -> String {
+    fn create_eml_headers(&self, boundary: &str) -> String {
         let date = Utc::now().format("%a, %d %b %Y %H:%M:%S %z").to_string();
         let mut headers = String::new();
 
         headers.push_str(&format!("Date: {}\r\n", date));
-        headers.push_str("From: EmlArchiver <archiver@example.com>\r\n");
-        headers.push_str("To: User <user@example.com>\r\n");
-        headers.push_str("Subject: Archived Files\r\n");
+        headers.push_str(&format!("From: {}\r\n", Self::encode_address(&self.from)));
+        headers.push_str(&format!("To: {}\r\n", Self::encode_address(&self.to)));
+        headers.push_str(&format!("Subject: {}\r\n", Self::encode_header_value(&self.subject)));
+        for (name, value) in &self.headers {
+            headers.push_str(&format!("{}: {}\r\n", name, Self::encode_header_value(value)));
+        }
         headers.push_str("MIME-Version: 1.0\r\n");
         headers.push_str(&format!("Content-Type: multipart/mixed; boundary=\"{}\"\r\n\r\n", boundary));
 
         headers
     }
 
-    /// Creates an introduction part for the EML file
-    fn create_introduction_part(boundary: &str) -> String {
-        let mut intro = String::new();
+    /// Encodes a header value with non-ASCII characters as RFC 2047 encoded
+    /// words, folded onto lines of their own. ASCII values are kept as they are.
+    fn encode_header_value(value: &str) -> String {
+        if value.is_ascii() {
+            return value.to_string();
+        }
+
+        let mut words = Vec::new();
+        let mut start = 0;
+        while start < value.len() {
+            let mut end = (start + ENCODED_WORD_BYTES).min(value.len());
+            while !value.is_char_boundary(end) {
+                end -= 1;
+            }
+            words.push(format!("=?UTF-8?B?{}?=", BASE64.encode(&value[start..end])));
+            start = end;
+        }
+        words.join("\r\n ")
+    }
+
+    /// Encodes an address such as `Name <user@example.com>`, where only the
+    /// display name may contain non-ASCII characters
+    fn encode_address(address: &str) -> String {
+        match address.rfind('<') {
+            Some(start) if !address.is_ascii() => {
+                let name = address[..start].trim().trim_matches('"');
+                format!("{} {}", Self::encode_header_value(name), &address[start..])
+            },
+            _ => Self::encode_header_value(address),
+        }
+    }
+
+    /// Creates the body of the introduction part, which is the configured
+    /// introduction or the fixed sentence followed by the context if there is one
+    fn create_introduction_body(introduction: Option<&str>, context: Option<&str>) -> String {
+        let mut body = String::new();
+
+        if let Some(introduction) = introduction {
+            for line in introduction.lines() {
+                body.push_str(line);
+                body.push_str("\r\n");
+            }
+            body.push_str("\r\n");
+            return body;
+        }
+
+        body.push_str("This is an archived collection of files created by EmlArchiver.\r\n\r\n");
+        if let Some(context) = context {
+            for line in context.lines() {
+                body.push_str(line);
+                body.push_str("\r\n");
+            }
+            body.push_str("\r\n");
+        }
+
+        body
+    }
 
+    /// Creates an introduction part for the EML file with the given body
+    fn create_introduction_part(boundary: &str, body: &str, strict_rfc: bool) -> String {
+        let transfer_encoding = TransferEncoding::for_text(LineEndings::Crlf.lines(body.as_bytes()), strict_rfc);
+        let mut intro = String::new();
         intro.push_str(&format!("--{}\r\n", boundary));
         intro.push_str("Content-Type: text/plain; charset=UTF-8\r\n");
-        intro.push_str("Content-Transfer-Encoding: 8bit\r\n\r\n");
-        intro.push_str("This is an archived collection of files created by EmlArchiver.\r\n\r\n");
+        intro.push_str(&format!("Content-Transfer-Encoding: {}\r\n\r\n", transfer_encoding.label()));
+        intro.push_str(&String::from_utf8_lossy(&Self::encode_text(body.as_bytes(), transfer_encoding)));
 
         intro
     }
 
-    /// Creates a file part header for the EML file
-    fn create_file_part_header(boundary: &str, file_name: &str) -> String {
+    /// Encodes text with a transfer encoding, borrowing it if it is sent as is
+    fn encode_text(content: &[u8], transfer_encoding: TransferEncoding) -> Cow<'_, [u8]> {
+        match transfer_encoding {
+            TransferEncoding::SevenBit | TransferEncoding::EightBit => Cow::Borrowed(content),
+            TransferEncoding::QuotedPrintable => Cow::Owned(encode_quoted_printable(content).into_bytes()),
+            TransferEncoding::Base64 => Cow::Owned(Self::encode_base64(content).into_bytes()),
+        }
+    }
+
+    /// Creates a file part header for the EML file, ending with the headers
+    /// that describe the original file
+    fn create_file_part_header(
+        boundary: &str,
+        archive_path: &str,
+        content_type: &str,
+        transfer_encoding: &str,
+        part_headers: &PartHeaders,
+    ) -> String {
         let mut header = String::new();
+        let mut disposition = Self::create_content_disposition(archive_path);
+        for (name, value) in &part_headers.disposition_params {
+            disposition.push_str(&format!(";\r\n {}={}", name, value));
+        }
 
         header.push_str(&format!("--{}\r\n", boundary));
-        header.push_str("Content-Type: text/plain; charset=UTF-8\r\n");
-        header.push_str("Content-Transfer-Encoding: 8bit\r\n");
-        header.push_str(&format!("Content-Disposition: attachment; filename=\"{}\"\r\n\r\n", file_name));
+        header.push_str(&format!("Content-Type: {}\r\n", content_type));
+        header.push_str(&format!("Content-Transfer-Encoding: {}\r\n", transfer_encoding));
+        header.push_str(&format!("Content-Disposition: {}\r\n", disposition));
+        header.push_str(&format!("Content-Location: {}\r\n", Self::percent_encode(archive_path, b"-._~/")));
+        for (name, value) in &part_headers.file_headers {
+            header.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        header.push_str("\r\n");
 
         header
     }
 
-    /// Reads a file and returns its contents as a UTF-8 string
-    async fn read_file_as_text(file_path: &PathBuf) -> Result<String, Box<dyn Error + Send + Sync>> {
-        let mut file = File::open(file_path).await
-            .map_err(|e| {
-                error!("Failed to open file {}: {}", file_path.display(), e);
-                Box::new(e) as Box<dyn Error + Send + Sync>
-            })?;
+    /// Creates the Content-Disposition value for an attachment. Names that are
+    /// not plain ASCII get an RFC 2231 `filename*` parameter next to an ASCII
+    /// fallback for readers that do not support it.
+    fn create_content_disposition(archive_path: &str) -> String {
+        let quoted = archive_path.replace('\\', "\\\\").replace('"', "\\\"");
 
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).await
-            .map_err(|e| {
-                error!("Failed to read file {}: {}", file_path.display(), e);
-                Box::new(e) as Box<dyn Error + Send + Sync>
-            })?;
+        if archive_path.bytes().all(|b| (b' '..=b'~').contains(&b)) {
+            return format!("attachment; filename=\"{}\"", quoted);
+        }
 
-        String::from_utf8(buffer)
-            .map_err(|e| {
-                error!("File {} is not valid UTF-8: {}", file_path.display(), e);
-                Box::new(e) as Box<dyn Error + Send + Sync>
-            })
+        let fallback: String = quoted.chars()
+            .map(|c| if (' '..='~').contains(&c) { c } else { '_' })
+            .collect();
+
+        format!(
+            "attachment;\r\n filename=\"{}\";\r\n filename*=UTF-8''{}",
+            fallback,
+            Self::percent_encode(archive_path, b"!#$&+-.^_`|~")
+        )
     }
 
-    /// Checks if a file is valid for inclusion in the archive
-    fn is_valid_file(file_path: &PathBuf) -> bool {
-        if !file_path.exists() {
-            warn!("File does not exist: {}", file_path.display());
-            return false;
+    /// Percent encodes every byte that is not alphanumeric or in `allowed`
+    fn percent_encode(value: &str, allowed: &[u8]) -> String {
+        let mut encoded = String::with_capacity(value.len());
+        for b in value.bytes() {
+            if b.is_ascii_alphanumeric() || allowed.contains(&b) {
+                encoded.push(b as char);
+            } else {
+                encoded.push_str(&format!("%{:02X}", b));
+            }
         }
+        encoded
+    }
 
-        if file_path.file_name().is_none() {
-            warn!("Could not extract filename from path: {}", file_path.display());
-            return false;
+    /// Writes a complete file part with the transfer encoding that suits the
+    /// content. The text is written in canonical form, with CRLF line breaks,
+    /// and its original line breaks are recorded in the part headers. Content
+    /// that contains a line which looks like the delimiter is base64 encoded
+    /// so that it cannot end the part early.
+    async fn write_file_part<W: AsyncWrite + Unpin>(
+        writer: &mut W,
+        boundary: &str,
+        archive_path: &str,
+        mime_type: &str,
+        content: &str,
+        mut part_headers: PartHeaders,
+        strict_rfc: bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let line_endings = LineEndings::detect(content.as_bytes());
+        let lines = line_endings.lines(content.as_bytes());
+
+        let content_type = format!("{}; charset=UTF-8", mime_type);
+        let transfer_encoding = if Self::contains_delimiter(content, boundary) {
+            warn!("File {} contains the MIME boundary, encoding it as base64", archive_path);
+            TransferEncoding::Base64
+        } else {
+            TransferEncoding::for_text(lines.clone(), strict_rfc)
+        };
+
+        let final_newline = if content.ends_with(['\n', '\r']) { "yes" } else { "no" };
+        part_headers.file_headers.push((LINE_ENDINGS_HEADER, line_endings.label().to_string()));
+        part_headers.file_headers.push((FINAL_NEWLINE_HEADER, final_newline.to_string()));
+        let header = Self::create_file_part_header(boundary, archive_path, &content_type, transfer_encoding.label(), &part_headers);
+        writer.write_all(header.as_bytes()).await?;
+
+        match transfer_encoding {
+            // written in chunks so that the canonical form is never held in memory
+            TransferEncoding::SevenBit | TransferEncoding::EightBit => {
+                let mut chunk = Vec::with_capacity(CANONICAL_CHUNK_SIZE);
+                for (index, line) in lines.enumerate() {
+                    if index > 0 {
+                        chunk.extend_from_slice(b"\r\n");
+                    }
+                    chunk.extend_from_slice(line);
+                    if chunk.len() >= CANONICAL_CHUNK_SIZE {
+                        writer.write_all(&chunk).await?;
+                        chunk.clear();
+                    }
+                }
+                writer.write_all(&chunk).await?;
+            },
+            TransferEncoding::QuotedPrintable | TransferEncoding::Base64 => {
+                let canonical = lines.collect::<Vec<_>>().join(&b"\r\n"[..]);
+                writer.write_all(&Self::encode_text(&canonical, transfer_encoding)).await?;
+            },
         }
+        // the line break in front of a delimiter belongs to the delimiter
+        writer.write_all(b"\r\n").await?;
 
-        true
+        Ok(())
     }
 
-    /// Creates the EML file at the specified path
-    async fn write_eml_file(output_path: &PathBuf, content: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
-        // Ensure parent directory exists
-        if let Some(parent) = output_path.parent() {
-            if !parent.exists() {
-                fs::create_dir_all(parent).await
-                    .map_err(|e| {
-                        error!("Failed to create directory {}: {}", parent.display(), e);
-                        Box::new(e) as Box<dyn Error + Send + Sync>
-                    })?;
-            }
-        }
+    /// Writes a binary file as a base64 part
+    async fn write_binary_part<W: AsyncWrite + Unpin>(
+        writer: &mut W,
+        boundary: &str,
+        archive_path: &str,
+        mime_type: &str,
+        content: &[u8],
+        part_headers: PartHeaders,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let header = Self::create_file_part_header(boundary, archive_path, mime_type, "base64", &part_headers);
+        writer.write_all(header.as_bytes()).await?;
+        writer.write_all(Self::encode_base64(content).as_bytes()).await?;
+        writer.write_all(b"\r\n").await?;
 
-        // Create and write to output file
-        let mut file = File::create(output_path).await
-            .map_err(|e| {
-                error!("Failed to create output file {}: {}", output_path.display(), e);
-                Box::new(e) as Box<dyn Error + Send + Sync>
-            })?;
+        Ok(())
+    }
 
-        file.write_all(content.as_bytes()).await
-            .map_err(|e| {
-                error!("Failed to write EML content: {}", e);
-                Box::new(e) as Box<dyn Error + Send + Sync>
-            })?;
+    /// Creates the headers that describe a file, the metadata ones only if
+    /// `metadata` is set and the extractor provided the values
+    fn create_part_headers(data: &FileData, length: u64, metadata: bool) -> PartHeaders {
+        let mut part_headers = PartHeaders::default();
+        part_headers.file_headers.push((LENGTH_HEADER, length.to_string()));
+        if let Some(charset) = &data.charset {
+            part_headers.file_headers.push((CHARSET_HEADER, charset.clone()));
+        }
+        if !metadata {
+            return part_headers;
+        }
 
-        Ok(())
+        if let Some(size) = data.size {
+            part_headers.disposition_params.push(("size", size.to_string()));
+            part_headers.file_headers.push((SIZE_HEADER, size.to_string()));
+        }
+        if let Some(modified) = data.modified {
+            let modified = DateTime::<Utc>::from(modified);
+            let date = modified.format("%a, %d %b %Y %H:%M:%S %z");
+            part_headers.disposition_params.push(("modification-date", format!("\"{}\"", date)));
+            part_headers.file_headers.push((MTIME_HEADER, modified.to_rfc3339_opts(SecondsFormat::AutoSi, true)));
+        }
+        if let Some(mode) = data.mode {
+            part_headers.file_headers.push((MODE_HEADER, format!("{:04o}", mode)));
+        }
+        if let Some(sha256) = &data.sha256 {
+            part_headers.file_headers.push((SHA256_HEADER, sha256.clone()));
+        }
+        if let Some(lines) = data.lines {
+            part_headers.file_headers.push((LINES_HEADER, lines.to_string()));
+        }
+
+        part_headers
     }
 
-    /// Process a single file and add it to the EML content
-    async fn process_file(
-        file_path: &PathBuf,
+    /// Process a single file and write it as a part to the EML output. Binaries
+    /// are written as base64, text files that are not valid UTF-8 are skipped,
+    /// failing writes are returned as errors.
+    async fn process_file<W: AsyncWrite + Unpin>(
+        &self,
+        loaded_file: &LoadedFile,
         boundary: &str,
-        eml_content: &mut String
+        writer: &mut W,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        if !Self::is_valid_file(file_path) {
-            return Ok(());
+        let archive_path = loaded_file.data.archive_path();
+        info!("Processing file: {}", archive_path);
+
+        if loaded_file.data.is_text != Some(true) {
+            let mime_type = loaded_file.data.mime_type.as_deref().unwrap_or("application/octet-stream");
+            let part_headers = Self::create_part_headers(&loaded_file.data, loaded_file.content.len() as u64, self.metadata);
+            return Self::write_binary_part(writer, boundary, &archive_path, mime_type, &loaded_file.content, part_headers).await;
         }
 
-        let file_name = file_path.file_name().unwrap().to_string_lossy();
-        info!("Processing file: {}", file_name);
+        // text files are always UTF-8 here, whatever their type is
+        let mime_type = loaded_file.data.mime_type.as_deref().unwrap_or("text/plain");
 
-        // Read file content as text
-        match Self::read_file_as_text(file_path).await {
-            Ok(text_content) => {
-                // Add file part header
-                eml_content.push_str(&Self::create_file_part_header(boundary, &file_name));
+        // the length is the one of the file before it was converted to UTF-8
+        let length = loaded_file.data.size.unwrap_or(loaded_file.content.len() as u64);
+        let part_headers = Self::create_part_headers(&loaded_file.data, length, self.metadata);
 
-                // Add file content
-                eml_content.push_str(&text_content);
-                eml_content.push_str("\r\n\r\n");
-
-                Ok(())
+        match loaded_file.text() {
+            Ok(text_content) => {
+                Self::write_file_part(writer, boundary, &archive_path, mime_type, text_content, part_headers, self.strict_rfc).await
+                    .map_err(|e| {
+                        error!("Failed to write EML content: {}", e);
+                        e
+                    })
             },
             Err(e) => {
-                warn!("Skipping file {}: {}", file_path.display(), e);
+                warn!("Skipping file {}: {}", loaded_file.data.path_to_file.display(), e);
                 Ok(())
             }
         }
@@ -164,40 +425,396 @@ impl EmlArchiver {
 impl Archiver for EmlArchiver {
     fn archive<'life>(
         &'life self,
-        output_path: &'life PathBuf,
-        file_paths: &'life [PathBuf],
-    ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send + 'life>> {
+        output_path: &'life Path,
+        mut files: mpsc::Receiver<LoadedFile>,
+    ) -> ArchiveFuture<'life> {
         Box::pin(async move {
             info!("Starting EML archive creation at: {}", output_path.display());
 
-            if file_paths.is_empty() {
-                warn!("No files to archive, creating empty EML file");
-            }
+            // Every part is written as soon as it is created so that only a
+            // single file is held in memory at a time
+            info!("Writing EML archive to: {}", output_path.display());
+            let mut writer = create_output_file(output_path).await?;
 
-            // Generate boundary and create headers
+            // Generate boundary and write headers
             let boundary = Self::generate_boundary();
-            let mut eml_content = Self::create_eml_headers(&boundary);
+            writer.write_all(self.create_eml_headers(&boundary).as_bytes()).await?;
 
             // Add introduction
-            eml_content.push_str(&Self::create_introduction_part(&boundary));
+            let introduction = Self::create_introduction_body(self.introduction.as_deref(), self.context.as_deref());
+            writer.write_all(Self::create_introduction_part(&boundary, &introduction, self.strict_rfc).as_bytes()).await?;
 
             // Process each file
-            for file_path in file_paths {
-                if let Err(e) = Self::process_file(file_path, &boundary, &mut eml_content).await {
-                    error!("Error processing file {}: {}", file_path.display(), e);
-                    // Continue with other files instead of failing completely
-                }
+            let mut file_count = 0;
+            while let Some(loaded_file) = files.recv().await {
+                self.process_file(&loaded_file, &boundary, &mut writer).await?;
+                file_count += 1;
             }
 
-            // Close the multipart message
-            eml_content.push_str(&format!("--{}--\r\n", boundary));
+            if file_count == 0 {
+                warn!("No files to archive, created empty EML file");
+            }
 
-            // Write the EML file
-            info!("Writing EML archive to: {}", output_path.display());
-            Self::write_eml_file(output_path, &eml_content).await?;
+            // Close the multipart message
+            writer.write_all(format!("--{}--\r\n", boundary).as_bytes()).await?;
+            writer.flush().await?;
 
             info!("Successfully created EML archive at: {}", output_path.display());
             Ok(())
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::path::PathBuf;
+    use mail_parser::{MessageParser, MimeHeaders};
+    use tokio::fs;
+    use crate::file_data::core::FileData;
+
+    /// Keeps track of the bytes allocated by a single thread and their peak,
+    /// so that tests can check how much memory an archive needs. Only the
+    /// thread that started tracking is counted, tests running at the same
+    /// time on other threads do not change its numbers.
+    struct TrackingAllocator;
+
+    thread_local! {
+        /// The bytes allocated by this thread and their peak, `None` while
+        /// the thread is not tracked.
+        static USAGE: Cell<Option<(isize, isize)>> = const { Cell::new(None) };
+    }
+
+    /// Adds a change in allocated bytes to the usage of the current thread
+    fn record_allocation(change: isize) {
+        let _ = USAGE.try_with(|usage| {
+            if let Some((allocated, peak)) = usage.get() {
+                let allocated = allocated + change;
+                usage.set(Some((allocated, peak.max(allocated))));
+            }
+        });
+    }
+
+    unsafe impl GlobalAlloc for TrackingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() {
+                record_allocation(layout.size() as isize);
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            record_allocation(-(layout.size() as isize));
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = System.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() {
+                record_allocation(new_size as isize - layout.size() as isize);
+            }
+            new_ptr
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: TrackingAllocator = TrackingAllocator;
+
+    /// Creates a complete message from in memory files with the given boundary
+    async fn create_message(boundary: &str, files: &[(&str, &str)]) -> Vec<u8> {
+        create_message_with(boundary, files, false).await
+    }
+
+    /// Creates a complete message, optionally in strict mode
+    async fn create_message_with(boundary: &str, files: &[(&str, &str)], strict_rfc: bool) -> Vec<u8> {
+        let mut message = EmlArchiver::with_options(&ArchiveOptions::default()).create_eml_headers(boundary).into_bytes();
+        let introduction = EmlArchiver::create_introduction_body(None, None);
+        message.extend_from_slice(EmlArchiver::create_introduction_part(boundary, &introduction, strict_rfc).as_bytes());
+        for (archive_path, content) in files {
+            EmlArchiver::write_file_part(&mut message, boundary, archive_path, "text/plain", content, PartHeaders::default(), strict_rfc).await.unwrap();
+        }
+        message.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+        message
+    }
+
+    /// Returns the canonical form of some text without its final line break,
+    /// which is how a MIME parser returns the text of a part
+    fn canonical(content: &str) -> String {
+        let line_endings = LineEndings::detect(content.as_bytes());
+        let lines: Vec<&[u8]> = line_endings.lines(content.as_bytes()).collect();
+        String::from_utf8(lines.join(&b"\r\n"[..])).unwrap().trim_end_matches("\r\n").to_string()
+    }
+
+    /// Parses a message with a real MIME parser and returns its attachments
+    fn parse_attachments(message: &[u8]) -> Vec<(String, String)> {
+        let parsed = MessageParser::default().parse(message).expect("message should parse");
+        parsed.attachments()
+            .map(|attachment| (
+                attachment.attachment_name().unwrap_or_default().to_string(),
+                String::from_utf8_lossy(attachment.contents()).trim_end_matches("\r\n").to_string(),
+            ))
+            .collect()
+    }
+
+    #[test]
+    fn create_introduction_part_adds_context_with_crlf_line_breaks() {
+        let body = EmlArchiver::create_introduction_body(None, Some("Branch: main\nCommit: abc"));
+        let intro = EmlArchiver::create_introduction_part("b", &body, false);
+        assert!(intro.ends_with("EmlArchiver.\r\n\r\nBranch: main\r\nCommit: abc\r\n\r\n"));
+    }
+
+    #[test]
+    fn create_introduction_part_encodes_non_ascii_context_in_strict_mode() {
+        let body = EmlArchiver::create_introduction_body(None, Some("Commit: abc Grüße"));
+        let intro = EmlArchiver::create_introduction_part("b", &body, true);
+        assert!(intro.contains("Content-Transfer-Encoding: quoted-printable\r\n"));
+        assert!(intro.ends_with("Commit: abc Gr=C3=BC=C3=9Fe\r\n\r\n"));
+    }
+
+    #[test]
+    fn create_introduction_body_replaces_the_sentence_and_context_with_the_introduction() {
+        let body = EmlArchiver::create_introduction_body(Some("Review the code.\nBe brief."), Some("Branch: main"));
+        assert_eq!("Review the code.\r\nBe brief.\r\n\r\n", body);
+    }
+
+    #[test]
+    fn configured_headers_parse_back_with_a_mime_parser() {
+        let options = ArchiveOptions {
+            from: Some("Jörg Müller <joerg@example.com>".to_string()),
+            to: Some("Reviewer <review@example.com>".to_string()),
+            subject: Some("Überprüfung der Änderungen an der Konfiguration und den Schnittstellen".to_string()),
+            headers: vec![("X-Ticket".to_string(), "ABC-123".to_string())],
+            ..ArchiveOptions::default()
+        };
+        let headers = EmlArchiver::with_options(&options).create_eml_headers("b");
+        assert!(headers.is_ascii());
+        assert!(headers.lines().all(|line| line.len() <= 78));
+        assert!(headers.contains("X-Ticket: ABC-123\r\nMIME-Version: 1.0\r\n"));
+
+        let message = format!("{}--b\r\nContent-Type: text/plain\r\n\r\nx\r\n--b--\r\n", headers);
+        let parsed = MessageParser::default().parse(message.as_bytes()).expect("message should parse");
+        assert_eq!(options.subject.as_deref(), parsed.subject());
+        let from = parsed.from().and_then(|from| from.first()).unwrap();
+        assert_eq!(Some("Jörg Müller"), from.name());
+        assert_eq!(Some("joerg@example.com"), from.address());
+        assert_eq!(Some("ABC-123"), parsed.header_raw("X-Ticket").map(str::trim));
+    }
+
+    #[test]
+    fn create_file_part_header_uses_the_mime_type() {
+        let header = EmlArchiver::create_file_part_header("b", "src/main.rs", "text/x-rust; charset=UTF-8", "8bit", &PartHeaders::default());
+        assert!(header.starts_with("--b\r\nContent-Type: text/x-rust; charset=UTF-8\r\n"));
+    }
+
+    #[test]
+    fn create_part_headers_adds_metadata_when_asked_for() {
+        let data = FileData {
+            is_text: Some(true),
+            size: Some(19),
+            modified: Some(std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(10_500)),
+            mode: Some(0o755),
+            lines: Some(2),
+            sha256: Some("ab".repeat(32)),
+            ..FileData::new(PathBuf::from("/src/run.sh"), PathBuf::from("run.sh"))
+        };
+
+        let plain = EmlArchiver::create_part_headers(&data, 19, false);
+        assert!(plain.disposition_params.is_empty());
+        assert_eq!(vec![(LENGTH_HEADER, "19".to_string())], plain.file_headers);
+
+        let part_headers = EmlArchiver::create_part_headers(&data, 19, true);
+        let header = EmlArchiver::create_file_part_header("b", "run.sh", "text/x-shellscript", "7bit", &part_headers);
+        assert!(header.contains(
+            "Content-Disposition: attachment; filename=\"run.sh\";\r\n size=19;\r\n modification-date=\"Thu, 01 Jan 1970 00:00:10 +0000\"\r\n"
+        ));
+        assert!(header.contains("X-Filefuser-Size: 19\r\nX-Filefuser-Mtime: 1970-01-01T00:00:10.500Z\r\nX-Filefuser-Mode: 0755\r\n"));
+        assert!(header.contains(&format!("X-Filefuser-Sha256: {}\r\nX-Filefuser-Lines: 2\r\n", "ab".repeat(32))));
+    }
+
+    #[tokio::test]
+    async fn binary_parts_are_base64_with_their_mime_type() {
+        let content: Vec<u8> = (0..=255u8).cycle().take(300).collect();
+        let mut message = EmlArchiver::with_options(&ArchiveOptions::default()).create_eml_headers("b").into_bytes();
+        EmlArchiver::write_binary_part(&mut message, "b", "img/logo.png", "image/png", &content, PartHeaders::default()).await.unwrap();
+        message.extend_from_slice(b"--b--\r\n");
+
+        let text = String::from_utf8(message.clone()).unwrap();
+        assert!(text.contains("Content-Type: image/png\r\nContent-Transfer-Encoding: base64\r\n"));
+        assert!(text.lines().all(|line| line.len() <= 76));
+
+        let parsed = MessageParser::default().parse(&message).unwrap();
+        let attachment = parsed.attachments().next().unwrap();
+        assert_eq!(Some("logo.png"), attachment.attachment_name().map(|name| name.rsplit('/').next().unwrap()));
+        assert_eq!(content.as_slice(), attachment.contents());
+    }
+
+    #[test]
+    fn generate_boundary_has_no_delimiter_prefix() {
+        assert!(EmlArchiver::generate_boundary().starts_with("boundary_"));
+    }
+
+    #[test]
+    fn contains_delimiter_only_matches_at_line_start() {
+        assert!(EmlArchiver::contains_delimiter("--b\n", "b"));
+        assert!(EmlArchiver::contains_delimiter("a\r\n--b--\r\n", "b"));
+        assert!(!EmlArchiver::contains_delimiter("a --b", "b"));
+    }
+
+    #[tokio::test]
+    async fn write_file_part_encodes_content_containing_the_delimiter() {
+        let content = "before\n--b\nafter\n";
+        let mut part = Vec::new();
+        EmlArchiver::write_file_part(&mut part, "b", "x.txt", "text/plain", content, PartHeaders::default(), false).await.unwrap();
+        let part = String::from_utf8(part).unwrap();
+        assert!(part.contains("Content-Transfer-Encoding: base64\r\n"));
+        assert!(!EmlArchiver::contains_delimiter(&part["--b\r\n".len()..], "b"));
+
+        let message = create_message("b", &[("x.txt", content), ("y.txt", "plain")]).await;
+        assert_eq!(
+            vec![("x.txt".to_string(), canonical(content)), ("y.txt".to_string(), "plain".to_string())],
+            parse_attachments(&message)
+        );
+    }
+
+    #[tokio::test]
+    async fn write_file_part_picks_the_transfer_encoding_for_the_content() {
+        for (content, strict_rfc, expected) in [
+            ("fn main() {}\n", false, "7bit"),
+            ("Viele Grüße\n", false, "8bit"),
+            ("Viele Grüße\n", true, "quoted-printable"),
+            ("日本語のテキスト\n", true, "base64"),
+        ] {
+            let mut part = Vec::new();
+            EmlArchiver::write_file_part(&mut part, "b", "x.txt", "text/plain", content, PartHeaders::default(), strict_rfc).await.unwrap();
+            let part = String::from_utf8(part).unwrap();
+            assert!(part.contains(&format!("Content-Transfer-Encoding: {}\r\n", expected)), "{:?}", content);
+        }
+    }
+
+    #[tokio::test]
+    async fn strict_mode_only_writes_short_ascii_lines_that_parse_back() {
+        let minified = format!("{}\n", "x = 1;".repeat(400));
+        let files = [("min.js", minified.as_str()), ("crlf.txt", "a\r\nb\r\n"), ("mac.txt", "old\rmac\r"), ("de.txt", "Viele Grüße\n")];
+        let message = create_message_with("b", &files, true).await;
+
+        assert!(message.is_ascii());
+        assert!(message.split(|byte| *byte == b'\n').all(|line| line.len() <= 998 && line.ends_with(b"\r") || line.is_empty()));
+        assert!(!message.windows(2).any(|pair| pair[0] == b'\r' && pair[1] != b'\n'));
+        assert_eq!(
+            files.iter().map(|(path, content)| (path.to_string(), canonical(content))).collect::<Vec<_>>(),
+            parse_attachments(&message)
+        );
+    }
+
+    #[test]
+    fn encode_base64_wraps_lines_at_76_characters() {
+        let encoded = EmlArchiver::encode_base64(&[0u8; 100]);
+        let lines: Vec<&str> = encoded.split("\r\n").collect();
+        assert_eq!(76, lines[0].len());
+        assert_eq!(BASE64.encode([0u8; 100]), lines.concat());
+    }
+
+    /// Sends the files at the given paths to the returned receiver, reading
+    /// each file only when the previous one has been received
+    fn send_files(paths: Vec<PathBuf>) -> mpsc::Receiver<LoadedFile> {
+        let (sender, receiver) = mpsc::channel(1);
+        tokio::spawn(async move {
+            for path in paths {
+                let content = fs::read(&path).await.unwrap();
+                let data = FileData {
+                    is_text: Some(true),
+                    size: Some(content.len() as u64),
+                    ..FileData::new(path.clone(), PathBuf::from(path.file_name().unwrap()))
+                };
+                sender.send(LoadedFile { data, content }).await.unwrap();
+            }
+        });
+        receiver
+    }
+
+    #[tokio::test]
+    async fn archive_of_an_archive_parses_with_a_mime_parser() {
+        let dir = std::env::temp_dir().join(format!("filefuser-eml-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).await.unwrap();
+
+        let inner_path = dir.join("inner.eml");
+        let outer_path = dir.join("outer.eml");
+        let text_path = dir.join("notes.txt");
+        fs::write(&text_path, "some notes\n").await.unwrap();
+
+        let archiver = EmlArchiver::with_options(&ArchiveOptions::default());
+        archiver.archive(&inner_path, send_files(vec![text_path.clone()])).await.unwrap();
+        archiver.archive(&outer_path, send_files(vec![inner_path.clone(), text_path])).await.unwrap();
+
+        let inner = fs::read_to_string(&inner_path).await.unwrap();
+        let outer = fs::read(&outer_path).await.unwrap();
+        let attachments = parse_attachments(&outer);
+        fs::remove_dir_all(&dir).await.unwrap();
+
+        assert_eq!(2, attachments.len());
+        assert_eq!(("inner.eml".to_string(), inner.trim_end_matches("\r\n").to_string()), attachments[0]);
+        assert_eq!(("notes.txt".to_string(), "some notes".to_string()), attachments[1]);
+    }
+
+    #[test]
+    fn create_content_disposition_quotes_ascii_paths() {
+        assert_eq!(
+            "attachment; filename=\"src/io/mod.rs\"",
+            EmlArchiver::create_content_disposition("src/io/mod.rs")
+        );
+        assert_eq!(
+            "attachment; filename=\"a \\\"b\\\".txt\"",
+            EmlArchiver::create_content_disposition("a \"b\".txt")
+        );
+    }
+
+    #[test]
+    fn create_content_disposition_encodes_non_ascii_paths() {
+        assert_eq!(
+            "attachment;\r\n filename=\"docs/r_sum_.md\";\r\n filename*=UTF-8''docs%2Fr%C3%A9sum%C3%A9.md",
+            EmlArchiver::create_content_disposition("docs/résumé.md")
+        );
+    }
+
+    #[tokio::test]
+    async fn archive_memory_does_not_grow_with_the_number_of_files() {
+        const FILE_SIZE: usize = 1024 * 1024;
+        const FILE_COUNT: usize = 16;
+
+        let dir = std::env::temp_dir().join(format!("filefuser-eml-{}", Uuid::new_v4()));
+        let output_path = dir.join("archive.eml");
+        let line = "a line of synthetic source code that is repeated over and over\n";
+        let content = line.repeat(FILE_SIZE / line.len());
+
+        // the test runtime runs the sender and the archiver on this thread,
+        // so all the copies of the content are counted
+        let (sender, receiver) = mpsc::channel(1);
+        USAGE.with(|usage| usage.set(Some((0, 0))));
+        tokio::spawn(async move {
+            for index in 0..FILE_COUNT {
+                let path = PathBuf::from(format!("file_{}.txt", index));
+                let data = FileData {
+                    is_text: Some(true),
+                    size: Some(content.len() as u64),
+                    ..FileData::new(path.clone(), path)
+                };
+                sender.send(LoadedFile { data, content: content.clone().into_bytes() }).await.unwrap();
+            }
+        });
+        EmlArchiver::with_options(&ArchiveOptions::default()).archive(&output_path, receiver).await.unwrap();
+        let (_, peak) = USAGE.with(|usage| usage.take()).unwrap();
+
+        let output_size = fs::metadata(&output_path).await.unwrap().len() as usize;
+        fs::remove_dir_all(&dir).await.unwrap();
+
+        assert!(output_size > FILE_SIZE * FILE_COUNT);
+        // one file being written, one waiting in the channel and one being created
+        assert!(
+            peak < (FILE_SIZE * 4) as isize,
+            "archiving {} bytes used {} bytes of memory", output_size, peak
+        );
+    }
+}
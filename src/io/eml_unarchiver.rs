@@ -0,0 +1,741 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::DateTime;
+use log::{info, warn, debug};
+use tokio::fs;
+use crate::io::core::{SkippedPart, UnarchiveFuture, UnarchiveReport, Unarchiver};
+use crate::file_data::charset::{byte_order_mark, from_utf8};
+use crate::file_data::extractor_impl::FileDataExtractorImpl;
+use crate::io::eml::{
+    CHARSET_HEADER, FINAL_NEWLINE_HEADER, LENGTH_HEADER, LINE_ENDINGS_HEADER, MODE_HEADER, MTIME_HEADER, SHA256_HEADER,
+};
+use crate::io::line_endings::LineEndings;
+use crate::io::transfer_encoding::decode_quoted_printable;
+
+/// Extracts the attachments of a multipart/mixed EML archive back into a
+/// directory tree.
+pub struct EmlUnarchiver;
+
+/// A single MIME part split into its unfolded headers and its raw body.
+struct MimePart<'a> {
+    headers: Vec<(String, String)>,
+    body: &'a [u8],
+}
+
+impl EmlUnarchiver {
+    /// Creates a new EmlUnarchiver instance
+    pub fn new() -> Self {
+        info!("Creating new EmlUnarchiver");
+        Self {}
+    }
+
+    /// Splits a message or part into its headers and body at the first empty line
+    fn split_headers(data: &[u8]) -> MimePart<'_> {
+        let mut line_start = 0;
+        while line_start < data.len() {
+            let line_end = Self::find_line_end(data, line_start);
+            let line = Self::trim_line_break(&data[line_start..line_end]);
+            if line.is_empty() {
+                let header_block = String::from_utf8_lossy(&data[..line_start]);
+                return MimePart {
+                    headers: Self::parse_headers(&header_block),
+                    body: &data[(line_end + 1).min(data.len())..],
+                };
+            }
+            line_start = line_end + 1;
+        }
+
+        MimePart {
+            headers: Self::parse_headers(&String::from_utf8_lossy(data)),
+            body: &[],
+        }
+    }
+
+    /// Parses a header block into name/value pairs, unfolding continuation lines
+    fn parse_headers(header_block: &str) -> Vec<(String, String)> {
+        let mut headers: Vec<(String, String)> = Vec::new();
+
+        for line in header_block.lines() {
+            if line.starts_with(' ') || line.starts_with('\t') {
+                if let Some((_, value)) = headers.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+                continue;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        headers
+    }
+
+    /// Returns the value of the first header with the given name (case insensitive)
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        headers.iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the value of a parameter in a structured header value. RFC 2231
+    /// extended (`name*`) and continued (`name*0`, `name*1*`, ...) parameters
+    /// take precedence over a plain `name` parameter.
+    fn header_param(value: &str, name: &str) -> Option<String> {
+        let params = Self::header_params(value);
+        let name = name.to_ascii_lowercase();
+
+        if let Some((_, extended)) = params.iter().find(|(param, _)| *param == format!("{}*", name)) {
+            return Some(Self::decode_extended_value(extended, true));
+        }
+
+        let mut sections: Vec<(usize, bool, &str)> = params.iter()
+            .filter_map(|(param, param_value)| {
+                let section = param.strip_prefix(&format!("{}*", name))?;
+                let (number, encoded) = match section.strip_suffix('*') {
+                    Some(number) => (number, true),
+                    None => (section, false),
+                };
+                Some((number.parse().ok()?, encoded, param_value.as_str()))
+            })
+            .collect();
+
+        if !sections.is_empty() {
+            sections.sort_by_key(|(number, _, _)| *number);
+            // only the first section carries the charset and language
+            let mut joined = String::new();
+            for (number, encoded, section) in sections {
+                if encoded {
+                    joined.push_str(&Self::decode_extended_value(section, number == 0));
+                } else {
+                    joined.push_str(section);
+                }
+            }
+            return Some(joined);
+        }
+
+        params.into_iter()
+            .find(|(param, _)| *param == name)
+            .map(|(_, param_value)| param_value)
+    }
+
+    /// Splits a structured header value into its parameters with lower case
+    /// names and unquoted values, skipping the leading token such as
+    /// `multipart/mixed` or `attachment`
+    fn header_params(value: &str) -> Vec<(String, String)> {
+        let mut params = Vec::new();
+        let mut rest = match value.find(';') {
+            Some(index) => &value[index + 1..],
+            None => return params,
+        };
+
+        loop {
+            rest = rest.trim_start();
+            let Some((param_name, after_name)) = rest.split_once('=') else {
+                return params;
+            };
+            let after_name = after_name.trim_start();
+
+            let (param_value, after_value) = if let Some(quoted) = after_name.strip_prefix('"') {
+                let mut unquoted = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((index, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            if let Some((_, escaped)) = chars.next() {
+                                unquoted.push(escaped);
+                            }
+                        },
+                        '"' => {
+                            end = index + 1;
+                            break;
+                        },
+                        _ => unquoted.push(c),
+                    }
+                }
+                (unquoted, &quoted[end..])
+            } else {
+                let end = after_name.find(';').unwrap_or(after_name.len());
+                (after_name[..end].trim().to_string(), &after_name[end..])
+            };
+
+            params.push((param_name.trim().to_ascii_lowercase(), param_value));
+
+            rest = match after_value.find(';') {
+                Some(index) => &after_value[index + 1..],
+                None => return params,
+            };
+        }
+    }
+
+    /// Decodes an RFC 2231 extended value such as `UTF-8'en'r%C3%A9sum%C3%A9`.
+    /// Only the first section of a continued value has the charset prefix.
+    fn decode_extended_value(value: &str, has_charset: bool) -> String {
+        let (charset, encoded) = match (has_charset, value.splitn(3, '\'').collect::<Vec<_>>().as_slice()) {
+            (true, [charset, _language, encoded]) => (charset.to_ascii_lowercase(), *encoded),
+            _ => ("utf-8".to_string(), value),
+        };
+
+        let bytes = Self::percent_decode(encoded);
+        match charset.as_str() {
+            "iso-8859-1" | "latin1" => bytes.iter().map(|b| *b as char).collect(),
+            _ => String::from_utf8_lossy(&bytes).into_owned(),
+        }
+    }
+
+    /// Decodes `%XX` escapes, leaving malformed escapes as they are
+    fn percent_decode(value: &str) -> Vec<u8> {
+        let bytes = value.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut index = 0;
+
+        while index < bytes.len() {
+            let hex = bytes.get(index + 1..index + 3)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+            match (bytes[index], hex) {
+                (b'%', Some(b)) => {
+                    decoded.push(b);
+                    index += 3;
+                },
+                (b, _) => {
+                    decoded.push(b);
+                    index += 1;
+                }
+            }
+        }
+
+        decoded
+    }
+
+    /// Splits a multipart body into the raw parts between its boundary delimiters
+    fn split_parts<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+        let delimiter = format!("--{}", boundary).into_bytes();
+        let mut parts = Vec::new();
+        let mut part_start: Option<usize> = None;
+        let mut line_start = 0;
+
+        while line_start < body.len() {
+            let line_end = Self::find_line_end(body, line_start);
+            let line = Self::trim_line_break(&body[line_start..line_end]);
+
+            if let Some(rest) = line.strip_prefix(delimiter.as_slice()) {
+                let is_closing = rest.starts_with(b"--");
+                if is_closing || rest.iter().all(|b| *b == b' ' || *b == b'\t') {
+                    if let Some(start) = part_start {
+                        // the line break in front of a delimiter belongs to the delimiter
+                        parts.push(Self::trim_line_break(&body[start..line_start]));
+                    }
+                    if is_closing {
+                        return parts;
+                    }
+                    part_start = Some((line_end + 1).min(body.len()));
+                }
+            }
+
+            line_start = line_end + 1;
+        }
+
+        if let Some(start) = part_start {
+            warn!("Archive is missing its closing boundary, keeping the last part as is");
+            parts.push(&body[start..]);
+        }
+
+        parts
+    }
+
+    /// Returns the index of the `\n` ending the line that starts at `start`
+    fn find_line_end(data: &[u8], start: usize) -> usize {
+        data[start..].iter()
+            .position(|b| *b == b'\n')
+            .map(|offset| start + offset)
+            .unwrap_or(data.len())
+    }
+
+    /// Removes a single trailing CRLF or LF
+    fn trim_line_break(data: &[u8]) -> &[u8] {
+        let data = data.strip_suffix(b"\n").unwrap_or(data);
+        data.strip_suffix(b"\r").unwrap_or(data)
+    }
+
+    /// Turns the file name of an attachment into a relative path that cannot
+    /// escape the target directory
+    fn safe_relative_path(file_name: &str) -> Result<PathBuf, String> {
+        if file_name.starts_with('/') || file_name.starts_with('\\') || Path::new(file_name).is_absolute() {
+            return Err(format!("absolute path '{}' is not allowed", file_name));
+        }
+
+        let mut path = PathBuf::new();
+        for component in file_name.split(['/', '\\']) {
+            match component {
+                "" | "." => continue,
+                ".." => return Err(format!("path traversal in '{}' is not allowed", file_name)),
+                _ if component.contains(':') || component.contains('\0') =>
+                    return Err(format!("invalid path component '{}'", component)),
+                _ => path.push(component),
+            }
+        }
+
+        if path.as_os_str().is_empty() {
+            return Err("empty file name".to_string());
+        }
+
+        Ok(path)
+    }
+
+    /// Decodes the body of a part according to its Content-Transfer-Encoding
+    fn decode_body(part: &MimePart<'_>) -> Result<Vec<u8>, String> {
+        let encoding = Self::header(&part.headers, "Content-Transfer-Encoding")
+            .unwrap_or("7bit")
+            .to_ascii_lowercase();
+
+        // archives written before parts recorded their length terminated
+        // every file body with an extra CRLF
+        let body = match Self::header(&part.headers, LENGTH_HEADER) {
+            Some(_) => part.body,
+            None => part.body.strip_suffix(b"\r\n").unwrap_or(part.body),
+        };
+
+        match encoding.as_str() {
+            "7bit" | "8bit" | "binary" => Ok(body.to_vec()),
+            "quoted-printable" => Ok(decode_quoted_printable(body)),
+            "base64" => {
+                let encoded: Vec<u8> = part.body.iter()
+                    .copied()
+                    .filter(|b| !b.is_ascii_whitespace())
+                    .collect();
+                BASE64.decode(encoded).map_err(|e| format!("invalid base64 content: {}", e))
+            },
+            other => Err(format!("unsupported Content-Transfer-Encoding '{}'", other)),
+        }
+    }
+
+    /// Turns the body of a part back into the exact content of the file, using
+    /// the headers that describe the original file. Parts without them, such
+    /// as those of other mail tools, are written as they are decoded.
+    fn restore_content(part: &MimePart<'_>) -> Result<Vec<u8>, String> {
+        let mut content = Self::decode_body(part)?;
+
+        if let Some(label) = Self::header(&part.headers, LINE_ENDINGS_HEADER) {
+            let line_endings = LineEndings::from_label(label)
+                .ok_or_else(|| format!("unknown line endings '{}'", label))?;
+            content = line_endings.restore(&content);
+        }
+
+        if let Some(final_newline) = Self::header(&part.headers, FINAL_NEWLINE_HEADER) {
+            let has_final_newline = content.ends_with(b"\n") || content.ends_with(b"\r");
+            if has_final_newline != final_newline.eq_ignore_ascii_case("yes") {
+                return Err(format!("final newline does not match {}: {}", FINAL_NEWLINE_HEADER, final_newline));
+            }
+        }
+
+        let length = Self::header(&part.headers, LENGTH_HEADER)
+            .map(|length| length.parse::<usize>().map_err(|_| format!("invalid {}: {}", LENGTH_HEADER, length)))
+            .transpose()?;
+
+        if let Some(charset) = Self::header(&part.headers, CHARSET_HEADER) {
+            content = from_utf8(&content, charset)?;
+            // the byte order mark is not kept in the text, the length tells
+            // if the file had one
+            if let Some(bom) = byte_order_mark(charset) {
+                if length == Some(content.len() + bom.len()) {
+                    content.splice(0..0, bom.iter().copied());
+                }
+            }
+        }
+
+        if let Some(length) = length.filter(|length| *length != content.len()) {
+            return Err(format!("restored {} bytes but the file had {}", content.len(), length));
+        }
+
+        if let Some(sha256) = Self::header(&part.headers, SHA256_HEADER) {
+            if !FileDataExtractorImpl::sha256_hex(&content).eq_ignore_ascii_case(sha256) {
+                return Err(format!("content does not match {}: {}", SHA256_HEADER, sha256));
+            }
+        }
+
+        Ok(content)
+    }
+
+    /// Gives an extracted file the modification time and permissions recorded
+    /// in its part. Failing to do so is only a warning, since the content has
+    /// been restored.
+    async fn restore_metadata(part: &MimePart<'_>, output_path: &Path) {
+        if let Some(mtime) = Self::header(&part.headers, MTIME_HEADER) {
+            let result = match DateTime::parse_from_rfc3339(mtime) {
+                Ok(mtime) => Self::set_modified(output_path, SystemTime::from(mtime)).await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(format!("invalid {} '{}': {}", MTIME_HEADER, mtime, e)),
+            };
+            if let Err(e) = result {
+                warn!("Could not restore the modification time of {}: {}", output_path.display(), e);
+            }
+        }
+
+        // the permissions come last since they may make the file read only
+        if let Some(mode) = Self::header(&part.headers, MODE_HEADER) {
+            let result = match u32::from_str_radix(mode, 8) {
+                Ok(mode) => Self::set_mode(output_path, mode).await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(format!("invalid {} '{}': {}", MODE_HEADER, mode, e)),
+            };
+            if let Err(e) = result {
+                warn!("Could not restore the permissions of {}: {}", output_path.display(), e);
+            }
+        }
+    }
+
+    /// Sets the modification time of a file
+    async fn set_modified(path: &Path, modified: SystemTime) -> std::io::Result<()> {
+        let file = fs::OpenOptions::new().write(true).open(path).await?;
+        file.into_std().await.set_modified(modified)
+    }
+
+    /// Sets the unix permission bits of a file
+    #[cfg(unix)]
+    async fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await
+    }
+
+    #[cfg(not(unix))]
+    async fn set_mode(_path: &Path, _mode: u32) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Checks that no existing component of a path below the target directory
+    /// is a symbolic link, which writing the file would follow out of it
+    async fn check_no_symlinks(target_dir: &Path, relative_path: &Path) -> Result<(), String> {
+        let mut path = target_dir.to_path_buf();
+        for component in relative_path.components() {
+            path.push(component);
+            match fs::symlink_metadata(&path).await {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    return Err(format!("{} is a symbolic link", path.display()));
+                },
+                Ok(_) => {},
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(format!("failed to inspect {}: {}", path.display(), e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Extracts a single attachment part, returning the path it was written to
+    async fn extract_part(
+        part: &MimePart<'_>,
+        file_name: &str,
+        target_dir: &Path,
+    ) -> Result<PathBuf, String> {
+        let relative_path = Self::safe_relative_path(file_name)?;
+        let content = Self::restore_content(part)?;
+        Self::check_no_symlinks(target_dir, &relative_path).await?;
+        let output_path = target_dir.join(&relative_path);
+
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent).await
+                .map_err(|e| format!("failed to create directory {}: {}", parent.display(), e))?;
+        }
+
+        fs::write(&output_path, &content).await
+            .map_err(|e| format!("failed to write {}: {}", output_path.display(), e))?;
+        Self::restore_metadata(part, &output_path).await;
+
+        Ok(relative_path)
+    }
+}
+
+impl Unarchiver for EmlUnarchiver {
+    fn unarchive<'life>(
+        &'life self,
+        archive_path: &'life Path,
+        target_dir: &'life Path,
+    ) -> UnarchiveFuture<'life> {
+        Box::pin(async move {
+            info!("Extracting EML archive {} into {}", archive_path.display(), target_dir.display());
+
+            let data = fs::read(archive_path).await
+                .map_err(|e| format!("failed to read archive {}: {}", archive_path.display(), e))?;
+
+            let message = Self::split_headers(&data);
+            let content_type = Self::header(&message.headers, "Content-Type")
+                .ok_or("archive has no Content-Type header")?;
+
+            if !content_type.to_ascii_lowercase().starts_with("multipart/") {
+                return Err(format!("archive is not a multipart message: {}", content_type).into());
+            }
+
+            let boundary = Self::header_param(content_type, "boundary")
+                .ok_or("archive Content-Type has no boundary")?;
+
+            let mut report = UnarchiveReport::default();
+
+            for (index, raw_part) in Self::split_parts(message.body, &boundary).into_iter().enumerate() {
+                let part = Self::split_headers(raw_part);
+
+                let disposition = Self::header(&part.headers, "Content-Disposition").unwrap_or("");
+                if !disposition.to_ascii_lowercase().starts_with("attachment") {
+                    debug!("Ignoring part {} without an attachment disposition", index);
+                    continue;
+                }
+
+                let location = Self::header(&part.headers, "Content-Location")
+                    .map(|location| String::from_utf8_lossy(&Self::percent_decode(location)).into_owned());
+
+                let file_name = match Self::header_param(disposition, "filename").or(location) {
+                    Some(file_name) => file_name,
+                    None => {
+                        let name = format!("part {}", index);
+                        warn!("Skipping {}: attachment has no filename", name);
+                        report.skipped.push(SkippedPart { name, reason: "attachment has no filename".to_string() });
+                        continue;
+                    }
+                };
+
+                match Self::extract_part(&part, &file_name, target_dir).await {
+                    Ok(path) => {
+                        info!("Extracted file: {}", path.display());
+                        report.extracted.push(path);
+                    },
+                    Err(reason) => {
+                        warn!("Skipping {}: {}", file_name, reason);
+                        report.skipped.push(SkippedPart { name: file_name, reason });
+                    }
+                }
+            }
+
+            info!("Extracted {} files, skipped {} parts", report.extracted.len(), report.skipped.len());
+            Ok(report)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+    use crate::file_data::core::{FileData, LoadedFile};
+    use crate::file_data::settings::LoadSettings;
+    use crate::io::core::{ArchiveOptions, Archiver};
+    use crate::io::eml::EmlArchiver;
+
+    /// Archives text files given as (path, content, original length, charset)
+    /// and extracts them again, returning the extracted files
+    async fn round_trip(files: Vec<(&str, Vec<u8>, usize, Option<&str>)>, strict_rfc: bool) -> Vec<(PathBuf, Vec<u8>)> {
+        let dir = std::env::temp_dir().join(format!("filefuser-round-trip-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let archive_path = dir.join("archive.eml");
+
+        let (sender, receiver) = mpsc::channel(files.len().max(1));
+        for (path, content, length, charset) in files {
+            let data = FileData {
+                is_text: Some(true),
+                size: Some(length as u64),
+                charset: charset.map(str::to_string),
+                ..FileData::new(dir.join(path), PathBuf::from(path))
+            };
+            sender.send(LoadedFile { data, content }).await.unwrap();
+        }
+        drop(sender);
+
+        let options = ArchiveOptions { strict_rfc, ..ArchiveOptions::default() };
+        EmlArchiver::with_options(&options).archive(&archive_path, receiver).await.unwrap();
+        let target_dir = dir.join("out");
+        let report = EmlUnarchiver::new().unarchive(&archive_path, &target_dir).await.unwrap();
+        assert!(report.skipped.is_empty(), "{:?}", report.skipped.iter().map(|part| &part.reason).collect::<Vec<_>>());
+
+        let mut extracted = Vec::new();
+        for path in report.extracted {
+            extracted.push((path.clone(), fs::read(target_dir.join(&path)).await.unwrap()));
+        }
+        fs::remove_dir_all(&dir).await.unwrap();
+        extracted
+    }
+
+    #[tokio::test]
+    async fn round_trip_restores_modification_time_and_permissions() {
+        use crate::file_data::core::FileDataExtractor;
+
+        let dir = std::env::temp_dir().join(format!("filefuser-round-trip-{}", Uuid::new_v4()));
+        fs::create_dir_all(dir.join("src")).await.unwrap();
+        let source = dir.join("src/run.sh");
+        fs::write(&source, "#!/bin/sh\necho hi\n").await.unwrap();
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::new(1_700_000_000, 123_456_789);
+        std::fs::File::options().write(true).open(&source).unwrap().set_modified(modified).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&source, std::fs::Permissions::from_mode(0o750)).unwrap();
+        }
+
+        let (sender, receiver) = mpsc::channel(1);
+        FileDataExtractorImpl::new(&dir.join("src"), 1).with_settings(LoadSettings::default().with_checksums(true))
+            .load_files(&[source], sender).await.unwrap();
+        let options = ArchiveOptions { metadata: true, ..ArchiveOptions::default() };
+        let archive_path = dir.join("archive.eml");
+        EmlArchiver::with_options(&options).archive(&archive_path, receiver).await.unwrap();
+        let report = EmlUnarchiver::new().unarchive(&archive_path, &dir.join("out")).await.unwrap();
+
+        let restored = std::fs::metadata(dir.join("out/run.sh")).unwrap();
+        let content = fs::read(dir.join("out/run.sh")).await.unwrap();
+        fs::remove_dir_all(&dir).await.unwrap();
+
+        assert_eq!(vec![PathBuf::from("run.sh")], report.extracted);
+        assert_eq!(b"#!/bin/sh\necho hi\n".to_vec(), content);
+        assert_eq!(modified, restored.modified().unwrap());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(0o750, restored.permissions().mode() & 0o7777);
+        }
+    }
+
+    #[test]
+    fn restore_content_rejects_a_checksum_mismatch() {
+        let headers = |sha256: &str| vec![
+            (LENGTH_HEADER.to_string(), "3".to_string()),
+            (SHA256_HEADER.to_string(), sha256.to_string()),
+        ];
+        let sha256 = FileDataExtractorImpl::sha256_hex(b"abc");
+
+        let part = MimePart { headers: headers(&sha256.to_ascii_uppercase()), body: b"abc" };
+        assert_eq!(b"abc".to_vec(), EmlUnarchiver::restore_content(&part).unwrap());
+        let part = MimePart { headers: headers(&FileDataExtractorImpl::sha256_hex(b"abd")), body: b"abc" };
+        assert!(EmlUnarchiver::restore_content(&part).is_err());
+    }
+
+    #[tokio::test]
+    async fn round_trip_restores_charsets_and_byte_order_marks() {
+        let (latin1, _, _) = encoding_rs::WINDOWS_1252.encode("Café crème\r\nNoël\r\n");
+        let utf16: Vec<u8> = [0xFF, 0xFE].into_iter()
+            .chain("héllo\n世界".encode_utf16().flat_map(|unit| unit.to_le_bytes()))
+            .collect();
+
+        let extracted = round_trip(vec![
+            ("latin1.txt", "Café crème\r\nNoël\r\n".as_bytes().to_vec(), latin1.len(), Some("windows-1252")),
+            ("utf16.txt", "héllo\n世界".as_bytes().to_vec(), utf16.len(), Some("UTF-16LE")),
+        ], false).await;
+
+        assert_eq!(vec![
+            (PathBuf::from("latin1.txt"), latin1.into_owned()),
+            (PathBuf::from("utf16.txt"), utf16),
+        ], extracted);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(48))]
+
+        #[test]
+        fn round_trip_restores_text_byte_for_byte(
+            content in proptest::collection::vec(
+                prop_oneof![
+                    4 => Just('\n'), 3 => Just('\r'), 2 => Just(' '), 1 => Just('\t'), 1 => Just('='),
+                    1 => Just('-'), 1 => Just('.'), 1 => Just('é'), 1 => Just('日'), 4 => Just('a'),
+                ],
+                0..1200,
+            ),
+            long_line in 0..1100usize,
+            strict_rfc in any::<bool>(),
+        ) {
+            // long lines make the archiver pick an encoding for the part
+            let content: String = content.into_iter().chain(std::iter::repeat_n('x', long_line)).collect();
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            let extracted = runtime.block_on(round_trip(
+                vec![("file.txt", content.clone().into_bytes(), content.len(), None)],
+                strict_rfc,
+            ));
+            prop_assert_eq!(vec![(PathBuf::from("file.txt"), content.into_bytes())], extracted);
+        }
+    }
+
+    #[test]
+    fn safe_relative_path_accepts_nested_paths() {
+        let path = EmlUnarchiver::safe_relative_path("src/io/mod.rs").unwrap();
+        assert_eq!(PathBuf::from("src/io/mod.rs"), path);
+    }
+
+    #[test]
+    fn safe_relative_path_rejects_traversal_and_absolute_paths() {
+        assert!(EmlUnarchiver::safe_relative_path("../etc/passwd").is_err());
+        assert!(EmlUnarchiver::safe_relative_path("src/../../x").is_err());
+        assert!(EmlUnarchiver::safe_relative_path("..\\x").is_err());
+        assert!(EmlUnarchiver::safe_relative_path("/etc/passwd").is_err());
+        assert!(EmlUnarchiver::safe_relative_path("C:/x").is_err());
+        assert!(EmlUnarchiver::safe_relative_path("").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unarchive_does_not_write_through_symbolic_links() {
+        let dir = std::env::temp_dir().join(format!("filefuser-symlink-{}", Uuid::new_v4()));
+        let target_dir = dir.join("target");
+        let outside_dir = dir.join("outside");
+        fs::create_dir_all(&target_dir).await.unwrap();
+        fs::create_dir_all(&outside_dir).await.unwrap();
+        fs::write(outside_dir.join("file.txt"), "outside\n").await.unwrap();
+        fs::symlink(&outside_dir, target_dir.join("link")).await.unwrap();
+        fs::symlink(outside_dir.join("file.txt"), target_dir.join("file.txt")).await.unwrap();
+
+        let archive_path = dir.join("archive.eml");
+        let part = |name: &str| format!(
+            "--b\r\nContent-Type: text/plain\r\nContent-Disposition: attachment; filename=\"{}\"\r\n\r\nowned\r\n", name
+        );
+        let archive = format!(
+            "Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n{}{}{}--b--\r\n",
+            part("link/passwd"), part("file.txt"), part("safe/file.txt")
+        );
+        fs::write(&archive_path, archive).await.unwrap();
+
+        let report = EmlUnarchiver::new().unarchive(&archive_path, &target_dir).await.unwrap();
+        let outside_written = fs::try_exists(outside_dir.join("passwd")).await.unwrap();
+        let outside_content = fs::read_to_string(outside_dir.join("file.txt")).await.unwrap();
+        fs::remove_dir_all(&dir).await.unwrap();
+
+        assert_eq!(vec![PathBuf::from("safe/file.txt")], report.extracted);
+        assert_eq!(2, report.skipped.len());
+        assert!(report.skipped.iter().all(|skipped| skipped.reason.contains("symbolic link")));
+        assert!(!outside_written);
+        assert_eq!("outside\n", outside_content);
+    }
+
+    #[test]
+    fn header_param_reads_quoted_and_plain_values() {
+        let value = "multipart/mixed; charset=utf-8; boundary=\"a \\\"b\\\"\"";
+        assert_eq!(Some("a \"b\"".to_string()), EmlUnarchiver::header_param(value, "boundary"));
+        assert_eq!(Some("utf-8".to_string()), EmlUnarchiver::header_param(value, "CHARSET"));
+        assert_eq!(None, EmlUnarchiver::header_param(value, "name"));
+    }
+
+    #[test]
+    fn header_param_decodes_rfc2231_values() {
+        let value = "attachment; filename=\"docs/r_sum_.md\"; filename*=UTF-8''docs%2Fr%C3%A9sum%C3%A9.md";
+        assert_eq!(Some("docs/résumé.md".to_string()), EmlUnarchiver::header_param(value, "filename"));
+
+        let continued = "attachment; filename*0*=UTF-8''%C3%A9; filename*1=\"/b.txt\"";
+        assert_eq!(Some("é/b.txt".to_string()), EmlUnarchiver::header_param(continued, "filename"));
+    }
+
+    #[test]
+    fn split_parts_strips_delimiter_line_breaks() {
+        let body = b"preamble\r\n--b\r\nA: 1\r\n\r\none\r\n--b\r\n\r\ntwo\n\r\n--b--\r\nepilogue";
+        let parts = EmlUnarchiver::split_parts(body, "b");
+        assert_eq!(vec![&b"A: 1\r\n\r\none"[..], &b"\r\ntwo\n"[..]], parts);
+    }
+
+    #[test]
+    fn decode_body_decodes_quoted_printable() {
+        let part = MimePart {
+            headers: vec![("Content-Transfer-Encoding".to_string(), "Quoted-Printable".to_string())],
+            body: b"caf=C3=A9=0A=\r\nlong =\r\nline=20\r\n",
+        };
+        assert_eq!("café\nlong line ".as_bytes(), EmlUnarchiver::decode_body(&part).unwrap());
+    }
+}
@@ -0,0 +1,27 @@
+use std::error::Error;
+use std::path::Path;
+use log::error;
+use tokio::fs::{self, File};
+use tokio::io::BufWriter;
+
+/// Creates the output file, and its parent directory if needed, and returns
+/// a buffered writer for it
+pub async fn create_output_file(output_path: &Path) -> Result<BufWriter<File>, Box<dyn Error + Send + Sync>> {
+    if let Some(parent) = output_path.parent() {
+        if !parent.exists() {
+            fs::create_dir_all(parent).await
+                .map_err(|e| {
+                    error!("Failed to create directory {}: {}", parent.display(), e);
+                    Box::new(e) as Box<dyn Error + Send + Sync>
+                })?;
+        }
+    }
+
+    let file = File::create(output_path).await
+        .map_err(|e| {
+            error!("Failed to create output file {}: {}", output_path.display(), e);
+            Box::new(e) as Box<dyn Error + Send + Sync>
+        })?;
+
+    Ok(BufWriter::new(file))
+}
@@ -0,0 +1,150 @@
+use std::error::Error;
+use std::path::Path;
+use std::str::Utf8Error;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use log::{info, warn};
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use crate::file_data::core::LoadedFile;
+use crate::io::core::{ArchiveFuture, Archiver};
+use crate::io::files::create_output_file;
+
+/// Writes one JSON record per file, either as a single JSON array or as
+/// JSON Lines with one record per line. Text is stored as it is and binaries
+/// as base64, which the `content_encoding` of a record tells apart.
+pub struct JsonArchiver {
+    /// Write JSON Lines instead of a JSON array.
+    lines: bool,
+}
+
+impl JsonArchiver {
+    /// Creates a new JsonArchiver that writes a JSON array
+    pub fn new() -> Self {
+        info!("Creating new JsonArchiver");
+        Self { lines: false }
+    }
+
+    /// Creates a new JsonArchiver that writes JSON Lines
+    pub fn lines() -> Self {
+        info!("Creating new JsonArchiver for JSON Lines");
+        Self { lines: true }
+    }
+
+    /// Creates the JSON record for a single file, which fails for text that
+    /// is not valid UTF-8
+    fn create_record(loaded_file: &LoadedFile) -> Result<Value, Utf8Error> {
+        let file_data = &loaded_file.data;
+        let (content_encoding, content) = match file_data.is_text {
+            Some(true) => ("utf-8", loaded_file.text()?.to_string()),
+            _ => ("base64", BASE64.encode(&loaded_file.content)),
+        };
+
+        Ok(json!({
+            "path": file_data.archive_path(),
+            "size": file_data.size,
+            "is_text": file_data.is_text,
+            "mime_type": file_data.mime_type,
+            "content_encoding": content_encoding,
+            "content": content,
+        }))
+    }
+
+    /// Returns what is written in front of the record with the given index
+    fn record_prefix(&self, index: usize) -> &'static str {
+        match (self.lines, index) {
+            (true, _) => "",
+            (false, 0) => "\n  ",
+            (false, _) => ",\n  ",
+        }
+    }
+}
+
+impl Archiver for JsonArchiver {
+    fn archive<'life>(
+        &'life self,
+        output_path: &'life Path,
+        mut files: mpsc::Receiver<LoadedFile>,
+    ) -> ArchiveFuture<'life> {
+        Box::pin(async move {
+            info!("Starting JSON archive creation at: {}", output_path.display());
+
+            let mut writer = create_output_file(output_path).await?;
+            if !self.lines {
+                writer.write_all(b"[").await?;
+            }
+
+            let mut index = 0;
+            while let Some(loaded_file) = files.recv().await {
+                let file_data = &loaded_file.data;
+                let archive_path = file_data.archive_path();
+                info!("Processing file: {}", archive_path);
+
+                let record = match Self::create_record(&loaded_file) {
+                    Ok(record) => record.to_string(),
+                    Err(e) => {
+                        warn!("Skipping file {}: {}", file_data.path_to_file.display(), e);
+                        continue;
+                    }
+                };
+                writer.write_all(self.record_prefix(index).as_bytes()).await?;
+                writer.write_all(record.as_bytes()).await
+                    .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?;
+                if self.lines {
+                    writer.write_all(b"\n").await?;
+                }
+                index += 1;
+            }
+
+            if !self.lines {
+                writer.write_all(if index == 0 { b"]\n" } else { b"\n]\n" }).await?;
+            }
+            writer.flush().await?;
+
+            info!("Successfully created JSON archive at: {}", output_path.display());
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::file_data::core::FileData;
+
+    #[test]
+    fn create_record_contains_path_size_and_content() {
+        let file_data = FileData {
+            is_text: Some(true),
+            size: Some(13),
+            mime_type: Some("text/x-rust".to_string()),
+            ..FileData::new(PathBuf::from("/tmp/src/main.rs"), PathBuf::from("src/main.rs"))
+        };
+        let loaded_file = LoadedFile { data: file_data, content: b"fn main() {}\n".to_vec() };
+
+        let record = JsonArchiver::create_record(&loaded_file).unwrap();
+        assert_eq!(
+            r#"{"path":"src/main.rs","size":13,"is_text":true,"mime_type":"text/x-rust","content_encoding":"utf-8","content":"fn main() {}\n"}"#,
+            record.to_string()
+        );
+    }
+
+    #[test]
+    fn create_record_stores_binaries_as_base64() {
+        let file_data = FileData {
+            is_text: Some(false),
+            size: Some(4),
+            mime_type: Some("image/png".to_string()),
+            ..FileData::new(PathBuf::from("/tmp/logo.png"), PathBuf::from("logo.png"))
+        };
+        let loaded_file = LoadedFile { data: file_data, content: b"\x89PNG".to_vec() };
+
+        let record = JsonArchiver::create_record(&loaded_file).unwrap();
+        assert_eq!(
+            r#"{"path":"logo.png","size":4,"is_text":false,"mime_type":"image/png","content_encoding":"base64","content":"iVBORw=="}"#,
+            record.to_string()
+        );
+    }
+}
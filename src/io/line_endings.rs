@@ -0,0 +1,143 @@
+/// The line breaks a text file uses. Text parts of a MIME message are in
+/// canonical form, with CRLF line breaks, so the original style is recorded
+/// to turn the canonical form back into the exact content of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEndings {
+    /// The content has no line breaks at all.
+    None,
+    Lf,
+    Crlf,
+    Cr,
+    /// The content uses more than one style, it is kept as it is.
+    Mixed,
+}
+
+impl LineEndings {
+    /// Detects the line breaks of some content
+    pub fn detect(content: &[u8]) -> Self {
+        let (mut lf, mut crlf, mut cr) = (0, 0, 0);
+        for (index, byte) in content.iter().enumerate() {
+            match byte {
+                b'\r' if content.get(index + 1) == Some(&b'\n') => crlf += 1,
+                b'\r' => cr += 1,
+                b'\n' if index > 0 && content[index - 1] == b'\r' => {}
+                b'\n' => lf += 1,
+                _ => {}
+            }
+        }
+
+        match (lf, crlf, cr) {
+            (0, 0, 0) => LineEndings::None,
+            (_, 0, 0) => LineEndings::Lf,
+            (0, _, 0) => LineEndings::Crlf,
+            (0, 0, _) => LineEndings::Cr,
+            _ => LineEndings::Mixed,
+        }
+    }
+
+    /// The label used in the `X-Filefuser-Line-Endings` header
+    pub fn label(&self) -> &'static str {
+        match self {
+            LineEndings::None => "none",
+            LineEndings::Lf => "lf",
+            LineEndings::Crlf => "crlf",
+            LineEndings::Cr => "cr",
+            LineEndings::Mixed => "mixed",
+        }
+    }
+
+    /// Parses a label as written by [`LineEndings::label`]
+    pub fn from_label(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "none" => Some(LineEndings::None),
+            "lf" => Some(LineEndings::Lf),
+            "crlf" => Some(LineEndings::Crlf),
+            "cr" => Some(LineEndings::Cr),
+            "mixed" => Some(LineEndings::Mixed),
+            _ => None,
+        }
+    }
+
+    /// The line break that is replaced by CRLF in the canonical form
+    fn line_break(&self) -> &'static [u8] {
+        match self {
+            LineEndings::Lf => b"\n",
+            LineEndings::Cr => b"\r",
+            _ => b"\r\n",
+        }
+    }
+
+    /// Splits content at its line breaks. The canonical form is the lines
+    /// joined with CRLF, content ending in a line break has an empty last line.
+    pub fn lines<'a>(&self, content: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + Clone + 'a {
+        split_at(content, self.line_break())
+    }
+
+    /// Turns content in canonical form back into content with these line breaks
+    pub fn restore(&self, canonical: &[u8]) -> Vec<u8> {
+        if self.line_break() == b"\r\n" {
+            return canonical.to_vec();
+        }
+        split_at(canonical, b"\r\n")
+            .collect::<Vec<_>>()
+            .join(self.line_break())
+    }
+}
+
+/// Splits content at every occurrence of a separator
+fn split_at<'a>(content: &'a [u8], separator: &'static [u8]) -> impl Iterator<Item = &'a [u8]> + Clone + 'a {
+    let mut rest = Some(content);
+    std::iter::from_fn(move || {
+        let current = rest?;
+        match find(current, separator) {
+            Some(position) => {
+                rest = Some(&current[position + separator.len()..]);
+                Some(&current[..position])
+            },
+            None => {
+                rest = None;
+                Some(current)
+            }
+        }
+    })
+}
+
+/// Returns the position of the first occurrence of a one or two byte separator
+fn find(content: &[u8], separator: &[u8]) -> Option<usize> {
+    let last = *separator.last()?;
+    let mut offset = 0;
+    while let Some(position) = content[offset..].iter().position(|byte| *byte == last) {
+        let end = offset + position + 1;
+        if end >= separator.len() && &content[end - separator.len()..end] == separator {
+            return Some(end - separator.len());
+        }
+        offset = end;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_tells_the_styles_apart() {
+        assert_eq!(LineEndings::None, LineEndings::detect(b"one line"));
+        assert_eq!(LineEndings::Lf, LineEndings::detect(b"a\nb\n"));
+        assert_eq!(LineEndings::Crlf, LineEndings::detect(b"a\r\nb"));
+        assert_eq!(LineEndings::Cr, LineEndings::detect(b"a\rb\r"));
+        assert_eq!(LineEndings::Mixed, LineEndings::detect(b"a\r\nb\n"));
+        assert_eq!(LineEndings::Mixed, LineEndings::detect(b"a\rb\n"));
+    }
+
+    #[test]
+    fn lines_and_restore_round_trip() {
+        for content in [&b"a\nb\n"[..], b"a\rb", b"a\r\nb\r\n", b"a\r\nb\n", b"", b"\n\n"] {
+            let line_endings = LineEndings::detect(content);
+            let canonical = line_endings.lines(content).collect::<Vec<_>>().join(&b"\r\n"[..]);
+            assert!(!canonical.windows(2).any(|pair| pair[1] == b'\n' && pair[0] != b'\r') || line_endings == LineEndings::Mixed);
+            assert_eq!(content, line_endings.restore(&canonical).as_slice());
+            assert_eq!(Some(line_endings), LineEndings::from_label(line_endings.label()));
+        }
+    }
+}
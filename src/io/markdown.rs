@@ -0,0 +1,176 @@
+use std::error::Error;
+use std::path::Path;
+use log::{info, warn};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use crate::file_data::core::LoadedFile;
+use crate::io::core::{ArchiveFuture, Archiver};
+use crate::io::files::create_output_file;
+
+/// Writes all files into a single Markdown document with a heading and a
+/// fenced code block per file.
+pub struct MarkdownArchiver;
+
+impl MarkdownArchiver {
+    /// Creates a new MarkdownArchiver instance
+    pub fn new() -> Self {
+        info!("Creating new MarkdownArchiver");
+        Self {}
+    }
+
+    /// Infers the language tag of a fenced code block from the file name
+    fn language_for(path: &Path) -> &'static str {
+        let file_name = path.file_name()
+            .map(|name| name.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        match file_name.as_str() {
+            "dockerfile" => return "dockerfile",
+            "makefile" => return "makefile",
+            "cmakelists.txt" => return "cmake",
+            _ => {}
+        }
+
+        let extension = path.extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "rs" => "rust",
+            "py" => "python",
+            "js" | "mjs" | "cjs" => "javascript",
+            "jsx" => "jsx",
+            "ts" | "mts" | "cts" => "typescript",
+            "tsx" => "tsx",
+            "go" => "go",
+            "java" => "java",
+            "kt" | "kts" => "kotlin",
+            "scala" => "scala",
+            "swift" => "swift",
+            "c" | "h" => "c",
+            "cc" | "cpp" | "cxx" | "hpp" | "hh" | "hxx" => "cpp",
+            "cs" => "csharp",
+            "rb" => "ruby",
+            "php" => "php",
+            "lua" => "lua",
+            "sh" | "bash" | "zsh" => "bash",
+            "ps1" => "powershell",
+            "sql" => "sql",
+            "html" | "htm" => "html",
+            "css" => "css",
+            "scss" => "scss",
+            "xml" => "xml",
+            "json" => "json",
+            "yaml" | "yml" => "yaml",
+            "toml" => "toml",
+            "md" | "markdown" => "markdown",
+            "mk" => "makefile",
+            _ => "",
+        }
+    }
+
+    /// Returns a backtick fence that is longer than any backtick run in the
+    /// content, so that the content can never close the code block early
+    fn fence_for(content: &str) -> String {
+        let mut longest_run = 0;
+        let mut current_run = 0;
+
+        for c in content.chars() {
+            if c == '`' {
+                current_run += 1;
+                longest_run = longest_run.max(current_run);
+            } else {
+                current_run = 0;
+            }
+        }
+
+        "`".repeat((longest_run + 1).max(3))
+    }
+
+    /// Creates the Markdown section for a single file
+    fn create_file_section(archive_path: &str, language: &str, content: &str) -> String {
+        let fence = Self::fence_for(content);
+        let mut section = String::new();
+
+        section.push_str(&format!("## {}\n\n", archive_path));
+        section.push_str(&format!("{}{}\n", fence, language));
+        section.push_str(content);
+        if !content.is_empty() && !content.ends_with('\n') {
+            section.push('\n');
+        }
+        section.push_str(&format!("{}\n\n", fence));
+
+        section
+    }
+}
+
+impl Archiver for MarkdownArchiver {
+    fn archive<'life>(
+        &'life self,
+        output_path: &'life Path,
+        mut files: mpsc::Receiver<LoadedFile>,
+    ) -> ArchiveFuture<'life> {
+        Box::pin(async move {
+            info!("Starting Markdown archive creation at: {}", output_path.display());
+
+            let mut writer = create_output_file(output_path).await?;
+            writer.write_all(b"# Archived Files\n\n").await?;
+
+            while let Some(loaded_file) = files.recv().await {
+                let file_data = &loaded_file.data;
+                let archive_path = file_data.archive_path();
+                info!("Processing file: {}", archive_path);
+
+                if file_data.is_text != Some(true) {
+                    warn!("Skipping binary file {}: not supported by the Markdown format", archive_path);
+                    continue;
+                }
+
+                let content = match loaded_file.text() {
+                    Ok(content) => content,
+                    Err(e) => {
+                        warn!("Skipping file {}: {}", file_data.path_to_file.display(), e);
+                        continue;
+                    }
+                };
+
+                let language = Self::language_for(&file_data.relative_path);
+                let section = Self::create_file_section(&archive_path, language, content);
+                writer.write_all(section.as_bytes()).await
+                    .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?;
+            }
+
+            writer.flush().await?;
+
+            info!("Successfully created Markdown archive at: {}", output_path.display());
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fence_for_grows_with_backtick_runs() {
+        assert_eq!("```", MarkdownArchiver::fence_for("no backticks"));
+        assert_eq!("```", MarkdownArchiver::fence_for("an `inline` span"));
+        assert_eq!("````", MarkdownArchiver::fence_for("```rust\nfn main() {}\n```\n"));
+        assert_eq!("``````", MarkdownArchiver::fence_for("`````"));
+    }
+
+    #[test]
+    fn language_for_uses_extension_and_file_name() {
+        assert_eq!("rust", MarkdownArchiver::language_for(Path::new("src/main.rs")));
+        assert_eq!("typescript", MarkdownArchiver::language_for(Path::new("app.TS")));
+        assert_eq!("dockerfile", MarkdownArchiver::language_for(Path::new("docker/Dockerfile")));
+        assert_eq!("", MarkdownArchiver::language_for(Path::new("LICENSE")));
+    }
+
+    #[test]
+    fn create_file_section_closes_unterminated_content() {
+        let section = MarkdownArchiver::create_file_section("a.rs", "rust", "fn main() {}");
+        assert_eq!("## a.rs\n\n```rust\nfn main() {}\n```\n\n", section);
+    }
+}
@@ -1,2 +1,10 @@
 pub mod core;
-pub mod eml;
\ No newline at end of file
+pub mod eml;
+pub mod eml_unarchiver;
+pub mod files;
+pub mod json;
+pub mod line_endings;
+pub mod markdown;
+pub mod registry;
+pub mod transfer_encoding;
+pub mod xml;
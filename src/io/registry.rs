@@ -0,0 +1,74 @@
+use log::info;
+use crate::io::core::{ArchiveOptions, Archiver};
+use crate::io::eml::EmlArchiver;
+use crate::io::json::JsonArchiver;
+use crate::io::markdown::MarkdownArchiver;
+use crate::io::xml::XmlArchiver;
+
+/// Creates a new instance of an archiver with the given options.
+pub type ArchiverFactory = fn(&ArchiveOptions) -> Box<dyn Archiver>;
+
+/// Keeps track of the available archivers by the name that is used to
+/// select them with `--type`.
+pub struct ArchiverRegistry {
+    archivers: Vec<(&'static str, ArchiverFactory)>,
+}
+
+impl ArchiverRegistry {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self { archivers: Vec::new() }
+    }
+
+    /// Creates a registry with all archivers that ship with filefuser
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::new();
+        registry.register("eml", |options| Box::new(EmlArchiver::with_options(options)));
+        registry.register("md", |_| Box::new(MarkdownArchiver::new()));
+        registry.register("xml", |_| Box::new(XmlArchiver::new()));
+        registry.register("json", |_| Box::new(JsonArchiver::new()));
+        registry.register("jsonl", |_| Box::new(JsonArchiver::lines()));
+        registry
+    }
+
+    /// Registers an archiver under the given name, replacing any archiver
+    /// that was registered with the same name before
+    pub fn register(&mut self, name: &'static str, factory: ArchiverFactory) {
+        self.archivers.retain(|(existing, _)| *existing != name);
+        self.archivers.push((name, factory));
+    }
+
+    /// Returns the names of all registered archivers
+    pub fn names(&self) -> Vec<&'static str> {
+        self.archivers.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// Creates the archiver registered under the given name. Unknown names
+    /// result in an error that lists the valid choices.
+    pub fn create(&self, name: &str, options: &ArchiveOptions) -> Result<Box<dyn Archiver>, String> {
+        info!("create: looking up archiver for type '{}'", name);
+        self.archivers.iter()
+            .find(|(registered, _)| registered.eq_ignore_ascii_case(name.trim()))
+            .map(|(_, factory)| factory(options))
+            .ok_or_else(|| format!("unknown type '{}', valid types are: {}", name, self.names().join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_known_type() {
+        let registry = ArchiverRegistry::with_defaults();
+        assert!(registry.create("eml", &ArchiveOptions::default()).is_ok());
+        assert!(registry.create("EML", &ArchiveOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn create_unknown_type_lists_valid_types() {
+        let registry = ArchiverRegistry::with_defaults();
+        let error = registry.create("nope", &ArchiveOptions::default()).err().unwrap();
+        assert_eq!(format!("unknown type 'nope', valid types are: {}", registry.names().join(", ")), error);
+    }
+}
@@ -0,0 +1,196 @@
+/// The longest line RFC 5322 allows, not counting the CRLF that ends it.
+const MAX_LINE_LENGTH: usize = 998;
+
+/// The longest line of quoted-printable output, as required by RFC 2045.
+const MAX_QUOTED_PRINTABLE_LINE_LENGTH: usize = 76;
+
+/// The Content-Transfer-Encoding of a MIME part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferEncoding {
+    SevenBit,
+    EightBit,
+    QuotedPrintable,
+    Base64,
+}
+
+impl TransferEncoding {
+    /// Picks the encoding for the lines of a text part, which are joined
+    /// with CRLF. Content is sent as is when that is valid, that is when its
+    /// lines fit in 998 octets and it has no bare CR, and otherwise encoded
+    /// as quoted-printable if it is mostly ASCII or as base64 if it is not.
+    ///
+    /// In strict mode the output must be accepted by any mail tooling, so
+    /// 8bit is never used and bare LF line breaks need encoding as well.
+    pub fn for_text<'a>(lines: impl Iterator<Item = &'a [u8]>, strict: bool) -> Self {
+        let (mut length, mut escaped, mut is_valid) = (0, 0, true);
+        for (index, line) in lines.enumerate() {
+            length += line.len() + if index > 0 { 2 } else { 0 };
+            escaped += line.iter().filter(|byte| !byte.is_ascii()).count();
+            is_valid = is_valid && is_valid_line(line, strict);
+        }
+
+        if is_valid {
+            if escaped == 0 {
+                return TransferEncoding::SevenBit;
+            }
+            if !strict {
+                return TransferEncoding::EightBit;
+            }
+        }
+
+        // quoted-printable triples every byte it has to escape, which is only
+        // worth keeping the text readable for when few bytes need it
+        if escaped * 3 > length {
+            TransferEncoding::Base64
+        } else {
+            TransferEncoding::QuotedPrintable
+        }
+    }
+
+    /// The value of the Content-Transfer-Encoding header
+    pub fn label(&self) -> &'static str {
+        match self {
+            TransferEncoding::SevenBit => "7bit",
+            TransferEncoding::EightBit => "8bit",
+            TransferEncoding::QuotedPrintable => "quoted-printable",
+            TransferEncoding::Base64 => "base64",
+        }
+    }
+}
+
+/// Checks if a line can be sent as is, which means it fits in 998 octets
+/// and has no NUL or CR. A bare LF is taken as a line break unless in strict
+/// mode.
+fn is_valid_line(line: &[u8], strict: bool) -> bool {
+    if line.contains(&0) || line.contains(&b'\r') || (strict && line.contains(&b'\n')) {
+        return false;
+    }
+    line.split(|byte| *byte == b'\n').all(|line| line.len() <= MAX_LINE_LENGTH)
+}
+
+/// Encodes content as quoted-printable. CRLF line breaks stay line breaks,
+/// other CR and LF bytes are escaped and followed by a soft line break so
+/// that the encoded lines still follow the lines of the content.
+pub fn encode_quoted_printable(content: &[u8]) -> String {
+    let mut encoded = String::with_capacity(content.len() + content.len() / 8);
+    let mut line_length = 0;
+
+    let mut index = 0;
+    while index < content.len() {
+        let byte = content[index];
+
+        if byte == b'\r' && content.get(index + 1) == Some(&b'\n') {
+            encoded.push_str("\r\n");
+            line_length = 0;
+            index += 2;
+            continue;
+        }
+
+        // whitespace at the end of a line would be removed in transport
+        let at_line_end = matches!(content.get(index + 1), None | Some(b'\r') | Some(b'\n'));
+        let literal = match byte {
+            b' ' | b'\t' => !at_line_end,
+            b'=' => false,
+            33..=126 => true,
+            _ => false,
+        };
+        let length = if literal { 1 } else { 3 };
+
+        // keep room for the `=` of a soft line break
+        if line_length + length > MAX_QUOTED_PRINTABLE_LINE_LENGTH - 1 {
+            encoded.push_str("=\r\n");
+            line_length = 0;
+        }
+
+        if literal {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("={:02X}", byte));
+        }
+        line_length += length;
+
+        if byte == b'\n' || byte == b'\r' {
+            encoded.push_str("=\r\n");
+            line_length = 0;
+        }
+        index += 1;
+    }
+
+    encoded
+}
+
+/// Decodes quoted-printable content. Soft line breaks are removed, escaped
+/// octets are decoded and malformed escapes are kept as they are.
+pub fn decode_quoted_printable(encoded: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+
+    let mut index = 0;
+    while index < encoded.len() {
+        let byte = encoded[index];
+        if byte != b'=' {
+            decoded.push(byte);
+            index += 1;
+            continue;
+        }
+
+        let rest = &encoded[index + 1..];
+        if rest.starts_with(b"\r\n") {
+            index += 3;
+        } else if rest.starts_with(b"\n") {
+            index += 2;
+        } else if let Some(value) = rest.get(..2)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()) {
+            decoded.push(value);
+            index += 3;
+        } else {
+            decoded.push(byte);
+            index += 1;
+        }
+    }
+
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::line_endings::LineEndings;
+
+    /// Picks the encoding for content that is sent without converting its line breaks
+    fn for_text(content: &[u8], strict: bool) -> TransferEncoding {
+        TransferEncoding::for_text(LineEndings::Crlf.lines(content), strict)
+    }
+
+    #[test]
+    fn for_text_sends_plain_content_as_is() {
+        assert_eq!(TransferEncoding::SevenBit, for_text(b"fn main() {}\n", false));
+        assert_eq!(TransferEncoding::EightBit, for_text("grüße\n".as_bytes(), false));
+        assert_eq!(TransferEncoding::SevenBit, for_text(b"a\r\nb\r\n", true));
+    }
+
+    #[test]
+    fn for_text_encodes_long_lines_and_bare_line_breaks() {
+        let minified = "x".repeat(MAX_LINE_LENGTH + 1);
+        assert_eq!(TransferEncoding::QuotedPrintable, for_text(minified.as_bytes(), false));
+        assert_eq!(TransferEncoding::QuotedPrintable, for_text(b"old\rmac\r", false));
+        assert_eq!(TransferEncoding::QuotedPrintable, for_text(b"unix\n", true));
+        assert_eq!(TransferEncoding::QuotedPrintable, for_text("Viele Grüße\r\n".as_bytes(), true));
+        assert_eq!(TransferEncoding::Base64, for_text("日本語のテキスト\n".as_bytes(), true));
+    }
+
+    #[test]
+    fn encode_quoted_printable_keeps_lines_short_and_escapes() {
+        let content = format!("{}\r\nend = \tx \r\ncaf\u{e9}\nlast ", "y".repeat(200));
+        let encoded = encode_quoted_printable(content.as_bytes());
+
+        assert!(encoded.split("\r\n").all(|line| line.len() <= MAX_QUOTED_PRINTABLE_LINE_LENGTH));
+        assert!(encoded.contains("\r\nend =3D \tx=20\r\ncaf=C3=A9=0A=\r\nlast=20"));
+        assert_eq!(content.as_bytes(), decode_quoted_printable(encoded.as_bytes()).as_slice());
+    }
+
+    #[test]
+    fn decode_quoted_printable_handles_soft_breaks_and_malformed_escapes() {
+        assert_eq!(b"abc= =3=zz\r\nd".to_vec(), decode_quoted_printable(b"a=\r\nb=\nc=3D =3=zz\r\nd"));
+    }
+}
@@ -0,0 +1,144 @@
+use std::error::Error;
+use std::path::Path;
+use log::{info, warn};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use crate::file_data::core::LoadedFile;
+use crate::io::core::{ArchiveFuture, Archiver};
+use crate::io::files::create_output_file;
+
+/// Writes all files into a `<documents>` element with one `<document>` per
+/// file, which is the structure LLM prompt templates commonly expect.
+pub struct XmlArchiver;
+
+impl XmlArchiver {
+    /// Creates a new XmlArchiver instance
+    pub fn new() -> Self {
+        info!("Creating new XmlArchiver");
+        Self {}
+    }
+
+    /// Escapes text for use in XML character data and attribute values
+    fn escape(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in Self::valid_xml_chars(text).chars() {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&apos;"),
+                _ => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    /// Wraps content in a CDATA section. Occurrences of `]]>` are split over
+    /// two CDATA sections since they would otherwise end the section early.
+    fn cdata(content: &str) -> String {
+        format!("<![CDATA[{}]]>", Self::valid_xml_chars(content).replace("]]>", "]]]]><![CDATA[>"))
+    }
+
+    /// Replaces characters that are not allowed anywhere in an XML 1.0
+    /// document, not even escaped, with the Unicode replacement character
+    fn valid_xml_chars(text: &str) -> String {
+        text.chars()
+            .map(|c| match c {
+                '\t' | '\n' | '\r' => c,
+                _ if c < ' ' || c == '\u{FFFE}' || c == '\u{FFFF}' => '\u{FFFD}',
+                _ => c,
+            })
+            .collect()
+    }
+
+    /// Creates the `<document>` element for a single file
+    fn create_document(index: usize, archive_path: &str, content: &str) -> String {
+        let path = Self::escape(archive_path);
+        let mut document = String::new();
+
+        document.push_str(&format!("<document index=\"{}\" path=\"{}\">\n", index, path));
+        document.push_str(&format!("<source>{}</source>\n", path));
+        document.push_str("<document_content>");
+        document.push_str(&Self::cdata(content));
+        document.push_str("</document_content>\n");
+        document.push_str("</document>\n");
+
+        document
+    }
+}
+
+impl Archiver for XmlArchiver {
+    fn archive<'life>(
+        &'life self,
+        output_path: &'life Path,
+        mut files: mpsc::Receiver<LoadedFile>,
+    ) -> ArchiveFuture<'life> {
+        Box::pin(async move {
+            info!("Starting XML archive creation at: {}", output_path.display());
+
+            let mut writer = create_output_file(output_path).await?;
+            writer.write_all(b"<documents>\n").await?;
+
+            let mut index = 0;
+            while let Some(loaded_file) = files.recv().await {
+                let file_data = &loaded_file.data;
+                let archive_path = file_data.archive_path();
+                info!("Processing file: {}", archive_path);
+
+                if file_data.is_text != Some(true) {
+                    warn!("Skipping binary file {}: not supported by the XML format", archive_path);
+                    continue;
+                }
+
+                let content = match loaded_file.text() {
+                    Ok(content) => content,
+                    Err(e) => {
+                        warn!("Skipping file {}: {}", file_data.path_to_file.display(), e);
+                        continue;
+                    }
+                };
+
+                index += 1;
+                let document = Self::create_document(index, &archive_path, content);
+                writer.write_all(document.as_bytes()).await
+                    .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?;
+            }
+
+            writer.write_all(b"</documents>\n").await?;
+            writer.flush().await?;
+
+            info!("Successfully created XML archive at: {}", output_path.display());
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cdata_splits_section_terminators() {
+        assert_eq!("<![CDATA[a]]]]><![CDATA[>b]]>", XmlArchiver::cdata("a]]>b"));
+        assert_eq!("<![CDATA[<tag> & ]]>", XmlArchiver::cdata("<tag> & "));
+    }
+
+    #[test]
+    fn escape_replaces_markup_and_invalid_characters() {
+        assert_eq!("a &amp; &lt;b&gt; &quot;c&quot; &apos;d&apos;", XmlArchiver::escape("a & <b> \"c\" 'd'"));
+        assert_eq!("x\u{FFFD}y", XmlArchiver::escape("x\u{1}y"));
+    }
+
+    #[test]
+    fn create_document_wraps_content() {
+        let document = XmlArchiver::create_document(1, "src/a&b.rs", "fn main() {}\n");
+        assert_eq!(
+            "<document index=\"1\" path=\"src/a&amp;b.rs\">\n\
+             <source>src/a&amp;b.rs</source>\n\
+             <document_content><![CDATA[fn main() {}\n]]></document_content>\n\
+             </document>\n",
+            document
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use std::io;
 use std::path::Path;
+use globset::{Glob, GlobBuilder};
 use log::info;
-use regex::Regex;
 
 
 pub fn is_just_filename(path: &Path) -> bool {
@@ -10,26 +10,46 @@ pub fn is_just_filename(path: &Path) -> bool {
 
 pub fn to_io_err_with_context(context: String) -> impl Fn(io::Error) -> io::Error {
     move |err: io::Error| {
-        io::Error::new(
-            io::ErrorKind::Other,
-            format!("{}: {}", context, err),
-        )
+        io::Error::other(format!("{}: {}", context, err))
     }
 }
 
+/// Returns the path relative to `base_dir` with `/` as separator, which is
+/// what glob patterns are matched against. Paths outside of `base_dir` give
+/// `None` so that patterns can never match the directories above it.
+pub fn to_glob_path(base_dir: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(base_dir).ok()?;
+    Some(relative.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
 
-pub fn simple_patterns_to_regexps(patterns: &[String]) -> Result<Vec<Regex>, Box<dyn std::error::Error>> {
-    let mut regexps = Vec::new();
-    for pattern in patterns {
-        regexps.push(simple_pattern_to_regex(pattern)?);
+/// Compiles a glob pattern that is matched against paths relative to the
+/// search directory, following the rules of `.gitignore` patterns:
+///
+/// * `*` and `?` never match `/`, `**` matches any number of directories
+/// * `[abc]`, `[!abc]` and `{a,b}` are character classes and alternations
+/// * a backslash escapes the next character, everything else is literal
+/// * a pattern without a `/` matches the file name at any depth
+/// * a pattern with a `/` is anchored at the search directory
+/// * a pattern ending in `/` matches everything below that directory
+pub fn glob_pattern_to_glob(pattern: &str) -> Result<Glob, Box<dyn std::error::Error>> {
+    let trimmed = pattern.trim();
+
+    let mut glob = trimmed.strip_prefix('/').unwrap_or(trimmed).to_string();
+    if glob.ends_with('/') {
+        glob.push_str("**");
+    }
+    if !trimmed.trim_end_matches('/').contains('/') {
+        glob = format!("**/{}", glob);
     }
-    Ok(regexps)
-}
 
-fn simple_pattern_to_regex(pattern: &str) -> Result<Regex, Box<dyn std::error::Error>> {
-    let regex = format!(".*{}", pattern.replace(".", "\\.").replace("*", ".*"));
-    info!("simple_pattern_to_regex: '{}' -> '{}'", pattern, regex);
-    Ok(Regex::new(&regex)?)
+    info!("glob_pattern_to_glob: '{}' -> '{}'", pattern, glob);
+    Ok(GlobBuilder::new(&glob)
+        .literal_separator(true)
+        .backslash_escape(true)
+        .build()?)
 }
 
 
@@ -42,22 +62,62 @@ fn simple_pattern_to_regex(pattern: &str) -> Result<Regex, Box<dyn std::error::E
 mod tests {
     use super::*;
 
+    fn matches(pattern: &str, path: &str) -> bool {
+        glob_pattern_to_glob(pattern).unwrap().compile_matcher().is_match(path)
+    }
+
+    #[test]
+    fn glob_pattern_without_slash_matches_file_names_at_any_depth() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", "src/io/mod.rs"));
+        assert!(!matches("*.rs", "foo.rsx.bak"));
+        assert!(!matches("*.rs", "src.rs/main.txt"));
+        assert!(!matches("*.txt", "a.txt.bak"));
+    }
+
     #[test]
-    fn simple_pattern_to_regex_test_simple_pattern() {
-        let result = simple_pattern_to_regex("*.txt").unwrap();
-        assert_eq!(".*.*\\.txt", result.as_str());
+    fn glob_pattern_with_slash_is_anchored() {
+        assert!(matches("src/*.rs", "src/main.rs"));
+        assert!(!matches("src/*.rs", "src/io/mod.rs"));
+        assert!(!matches("src/*.rs", "other/src/main.rs"));
+        assert!(matches("/Cargo.toml", "Cargo.toml"));
+        assert!(!matches("/Cargo.toml", "sub/Cargo.toml"));
     }
 
     #[test]
-    fn simple_pattern_to_regex_test_path_pattern() {
-        let result = simple_pattern_to_regex("test/*/*.rs").unwrap();
-        assert_eq!(".*test/.*/.*\\.rs", result.as_str());
+    fn glob_pattern_double_star_and_directories() {
+        assert!(matches("src/**/*.rs", "src/main.rs"));
+        assert!(matches("src/**/*.rs", "src/io/eml/mod.rs"));
+        assert!(matches("**/tests/**", "crate/tests/it.rs"));
+        assert!(matches("docs/", "docs/guide/intro.md"));
+        assert!(!matches("docs/", "src/docs.rs"));
     }
 
     #[test]
-    fn simple_pattern_to_regex_test_empty_string() {
-        let result = simple_pattern_to_regex("").unwrap();
-        assert_eq!(".*", result.as_str());
+    fn glob_pattern_wildcards_classes_and_alternation() {
+        assert!(matches("?.rs", "a.rs"));
+        assert!(!matches("?.rs", "ab.rs"));
+        assert!(matches("[ab].rs", "b.rs"));
+        assert!(!matches("[!ab].rs", "a.rs"));
+        assert!(matches("*.{rs,toml}", "Cargo.toml"));
+        assert!(!matches("*.{rs,toml}", "Cargo.lock"));
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn glob_pattern_treats_regex_metacharacters_literally() {
+        assert!(matches("c++/*.cc", "c++/main.cc"));
+        assert!(!matches("c++/*.cc", "cc/main.cc"));
+        assert!(matches("(draft)*.md", "(draft) notes.md"));
+        assert!(matches("$HOME.^txt", "$HOME.^txt"));
+        assert!(matches("\\[x\\].txt", "[x].txt"));
+        assert!(!matches("\\[x\\].txt", "x.txt"));
+    }
+
+    #[test]
+    fn to_glob_path_is_relative_to_base_dir() {
+        let base = Path::new("/work/repo");
+        assert_eq!(Some("src/main.rs".to_string()), to_glob_path(base, Path::new("/work/repo/src/main.rs")));
+        assert_eq!(None, to_glob_path(base, Path::new("/work/other/main.rs")));
+    }
+
+}
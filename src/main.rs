@@ -1,12 +1,22 @@
 
 use std::error::Error;
+use std::path::PathBuf;
 use std::process::exit;
+use chrono::Local;
 use log::{error, info, warn};
-use crate::dirscan::{get_files};
-use crate::file_data::core::{FileData, FileDataExtractor, only_errors, only_binaries, only_text_files};
+use crate::dirscan::{filter_files, get_files, get_files_from};
+use crate::file_data::charset::EncodingRules;
+use crate::file_data::mime::MimeTypes;
+use crate::file_data::core::{FileData, FileDataExtractor, only_errors, only_binaries, only_converted, only_text_files};
 use crate::file_data::extractor_impl::FileDataExtractorImpl;
-use crate::io::core::Archiver;
-use crate::io::eml::EmlArchiver;
+use crate::file_data::settings::LoadSettings;
+use crate::args::Args;
+use crate::config::render_template;
+use crate::file_data::git_rev_extractor::GitRevFileDataExtractor;
+use crate::git::{get_git_files, get_repo_context, get_rev_files};
+use crate::io::core::{ArchiveOptions, Archiver, Unarchiver};
+use crate::io::eml_unarchiver::EmlUnarchiver;
+use crate::io::registry::ArchiverRegistry;
 
 mod args;
 mod dirscan;
@@ -15,25 +25,157 @@ mod logging;
 mod file_data;
 mod io;
 mod fs;
+mod pipeline;
+mod git;
+mod config;
+
+async fn unfuse(args: &Args) -> Result<(), Box<dyn Error>> {
+    info!("unfuse: archive file path: {:?}", args.output_file_path);
+    info!("unfuse: target dir: {:?}", args.search_dir);
+
+    let unarchiver: Box<dyn Unarchiver> = Box::new(EmlUnarchiver::new());
+
+    let report = match unarchiver.unarchive(&args.output_file_path, &args.search_dir).await {
+        Ok(report) => report,
+        Err(e) => return Err(Box::<dyn Error>::from(e.to_string())),
+    };
+
+    info!("unfuse: extracted {:?} files", report.extracted.len());
+    eprintln!("Extracted {} files into {}", report.extracted.len(), args.search_dir.display());
+    for skipped in &report.skipped {
+        eprintln!("Skipped {}: {}", skipped.name, skipped.reason);
+    }
+
+    // a partly restored tree must not look like a success to scripts
+    if !report.skipped.is_empty() {
+        return Err(format!("skipped {} parts of the archive", report.skipped.len()).into());
+    }
+
+    Ok(())
+}
+
+/// Collects the settings for the archiver from the arguments, the
+/// introduction lists the files that go into the archive
+async fn archive_options(
+    args: &Args,
+    extractor: &dyn FileDataExtractor,
+    files: &[PathBuf],
+) -> Result<ArchiveOptions, Box<dyn Error>> {
+    let mut options = ArchiveOptions {
+        strict_rfc: args.strict_rfc,
+        metadata: args.metadata,
+        from: args.from.clone(),
+        to: args.to.clone(),
+        subject: args.subject.clone(),
+        headers: args.headers.clone(),
+        ..ArchiveOptions::default()
+    };
+
+    if let Some(count) = args.git_context {
+        // the archive is still useful without the context, so it is skipped
+        // when the search dir is not inside a git repository
+        match get_repo_context(&args.search_dir, args.git_rev.as_deref(), count) {
+            Ok(context) => options.context = Some(context.describe()),
+            Err(e) => warn!("archive_options: no git context: {}", e),
+        }
+    }
+
+    if let Some(template) = &args.intro {
+        // the introduction is written first, so listing the archived files
+        // means loading them all once before the archive is written
+        let archived_files = if template.contains("{files}") || template.contains("{file_count}") {
+            info!("archive_options: loading the files to list them in the introduction");
+            pipeline::list_archived_files(extractor, files).await
+                .map_err(|e| Box::<dyn Error>::from(e.to_string()))?
+        } else {
+            Vec::new()
+        };
+        options.introduction = Some(render_introduction(template, options.context.as_deref(), &archived_files));
+    }
+
+    Ok(options)
+}
+
+/// Fills in the placeholders of the introduction template. The context is
+/// added at the end if the template does not say where it goes.
+fn render_introduction(template: &str, context: Option<&str>, files: &[FileData]) -> String {
+    let file_list = files.iter()
+        .map(|file_data| file_data.archive_path())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let date = Local::now().format("%Y-%m-%d").to_string();
+    let file_count = files.len().to_string();
+    let values = [
+        ("context", context.unwrap_or_default()),
+        ("date", date.as_str()),
+        ("file_count", file_count.as_str()),
+        ("files", file_list.as_str()),
+    ];
+
+    let mut introduction = render_template(template, &values);
+    if let Some(context) = context.filter(|_| !template.contains("{context}")) {
+        introduction.push_str("\n\n");
+        introduction.push_str(context);
+    }
+    introduction
+}
 
 async fn start() -> Result<(), Box<dyn Error>> {
     let args = args::parse_args()?;
 
+    if args.unfuse {
+        return unfuse(&args).await;
+    }
+
     info!("start: output file path: {:?}", args.output_file_path);
     info!("start: file type: {:?}", args.file_type);
     info!("start: patterns: {:?}", args.patterns);
+    info!("start: exclude patterns: {:?}", args.exclude_patterns);
+    info!("start: jobs: {:?}", args.jobs);
 
-    let files = get_files(&args.search_dir, &args.patterns)?;
+    let files = match (&args.files_from, &args.git_selection, &args.git_rev) {
+        (Some(source), _, _) => get_files_from(&args.search_dir, source, args.null_delimited, &args.patterns, &args.exclude_patterns)?,
+        (None, Some(selection), _) => {
+            let git_files = get_git_files(&args.search_dir, selection)?;
+            filter_files(&args.search_dir, git_files, &args.patterns, &args.exclude_patterns)?
+        },
+        (None, None, Some(rev)) => {
+            let rev_files = get_rev_files(&args.search_dir, rev)?;
+            filter_files(&args.search_dir, rev_files, &args.patterns, &args.exclude_patterns)?
+        },
+        (None, None, None) => get_files(&args.search_dir, &args.patterns, &args.exclude_patterns, args.use_ignore_files)?,
+    };
     info!("start: got {:?} files", files.len());
 
-    let file_data_extractor: Box<dyn FileDataExtractor> =
-        Box::new(FileDataExtractorImpl::new());
+    let settings = LoadSettings::default()
+        .with_encodings(EncodingRules::parse(&args.encodings)?)
+        .with_mime_types(MimeTypes::with_defaults().with_mappings(&args.mime_types)?)
+        .with_binary_size_limit(args.binary_size_limit)
+        .with_checksums(args.metadata);
+    let file_data_extractor: Box<dyn FileDataExtractor> = match &args.git_rev {
+        Some(rev) => Box::new(GitRevFileDataExtractor::new(&args.search_dir, rev).with_settings(settings)),
+        None => Box::new(FileDataExtractorImpl::new(&args.search_dir, args.jobs).with_settings(settings)),
+    };
 
-    let file_data_list = match file_data_extractor.get_file_data(&files).await {
-        Ok(data) => data,
-        Err(e) => return Err(Box::<dyn Error>::from(e.to_string())),
+    let options = archive_options(&args, file_data_extractor.as_ref(), &files).await?;
+    let archiver: Box<dyn Archiver> = ArchiverRegistry::with_defaults()
+        .create(&args.file_type, &options)?;
+
+    info!("start: archiving text files into the archive: {:?}", args.output_file_path);
+    let outcome = match pipeline::run(
+        file_data_extractor.as_ref(),
+        archiver.as_ref(),
+        &files,
+        &args.output_file_path,
+    ).await {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            remove_incomplete_archive(&args);
+            return Err(Box::<dyn Error>::from(e.to_string()));
+        },
     };
 
+    let file_data_list = outcome.file_data_list;
     info!("start: got {:?} file data's", file_data_list.len());
 
     // extract all the error lists from the file_data_list
@@ -43,43 +185,64 @@ async fn start() -> Result<(), Box<dyn Error>> {
     }
 
     if !errors.is_empty() {
-
+        // the archive stopped at the first error so it is incomplete
+        remove_incomplete_archive(&args);
         exit(1);
     }
     // from this point we know the list is only successful results
 
-    let binary_files: Vec<FileData> = only_binaries(&file_data_list);
-    if !binary_files.is_empty() {
-        warn!("start: found {:?} binary files", binary_files.len());
-        for binary_file in &binary_files {
-            warn!("start: ignoring binary: {:?}", binary_file);
+    // only the binaries that were left out are worth a warning
+    let (included_binaries, ignored_binaries): (Vec<FileData>, Vec<FileData>) = only_binaries(&file_data_list)
+        .into_iter()
+        .partition(|binary_file| args.binary_size_limit.is_some_and(|limit| binary_file.size.unwrap_or(0) <= limit));
+    if !included_binaries.is_empty() {
+        info!("start: including {:?} binary files", included_binaries.len());
+        for binary_file in &included_binaries {
+            info!("start: including binary: {}", binary_file.archive_path());
+        }
+    }
+    if !ignored_binaries.is_empty() {
+        warn!("start: ignoring {:?} binary files", ignored_binaries.len());
+        for binary_file in &ignored_binaries {
+            match args.binary_size_limit {
+                Some(limit) => warn!("start: ignoring binary larger than {} bytes: {:?}", limit, binary_file),
+                None => warn!("start: ignoring binary: {:?}", binary_file),
+            }
         }
     }
 
     let text_files: Vec<FileData> = only_text_files(&file_data_list);
     info!("start: found {:?} text files", text_files.len());
 
-    let archiver: Box<dyn Archiver> = Box::new(EmlArchiver::new());
-
-    let text_paths: Vec<std::path::PathBuf> = text_files.iter()
-        .map(|file_data| file_data.path_to_file.clone())
-        .collect();
-
-    info!("start: archiving {:?} text files into the archive: {:?}", text_paths.len(), args.output_file_path);
-    let archive_result = archiver.archive(&args.output_file_path, &text_paths).await;
+    let converted_files: Vec<FileData> = only_converted(&file_data_list);
+    if !converted_files.is_empty() {
+        info!("start: converted {:?} text files to UTF-8", converted_files.len());
+        for converted_file in &converted_files {
+            info!("start: converted from {}: {}", converted_file.charset.as_deref().unwrap_or_default(), converted_file.archive_path());
+        }
+    }
 
-    match archive_result {
+    match outcome.archive_result {
         Ok(_) => {
             info!("start: archive success");
         },
         Err(e) => {
             error!("start: archive error: {:?}", e);
+            remove_incomplete_archive(&args);
+            return Err(Box::<dyn Error>::from(format!("failed to write archive: {}", e)));
         }
     }
 
     Ok(())
 }
 
+/// Removes an archive that was not written completely
+fn remove_incomplete_archive(args: &Args) {
+    if let Err(e) = std::fs::remove_file(&args.output_file_path) {
+        warn!("remove_incomplete_archive: could not remove incomplete archive: {:?}", e);
+    }
+}
+
 #[tokio::main]
 async fn main() {
 
@@ -88,5 +251,6 @@ async fn main() {
 
     if let Err(e) = start().await {
         eprintln!("Error: {}", e);
+        exit(1);
     }
 }
@@ -0,0 +1,105 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use log::info;
+use tokio::sync::mpsc;
+use crate::file_data::core::{FileData, FileDataExtractor, LoadedFile};
+use crate::io::core::Archiver;
+
+/// The number of loaded files that can wait between two stages of the
+/// pipeline. Kept small since every waiting file holds its whole content.
+const CHANNEL_CAPACITY: usize = 1;
+
+/// The outcome of running the pipeline.
+pub struct PipelineOutcome {
+    /// Metadata for every file that was loaded, including errors and binaries.
+    pub(crate) file_data_list: Vec<FileData>,
+    /// The result of writing the archive.
+    pub(crate) archive_result: Result<(), Box<dyn Error + Send + Sync>>,
+}
+
+/// Reads every file once and hands the text files, and the binaries whose
+/// content was loaded, to the archiver while they are being read. Each file
+/// is opened a single time and only a few files are held in memory at once.
+///
+/// Once a file with an error has been seen no more files are passed on to the
+/// archiver, but the remaining files are still loaded so that all errors can
+/// be reported.
+pub async fn run(
+    extractor: &dyn FileDataExtractor,
+    archiver: &dyn Archiver,
+    file_paths: &[PathBuf],
+    output_path: &Path,
+) -> Result<PipelineOutcome, Box<dyn Error + Send + Sync>> {
+    let (loaded_sender, mut loaded_receiver) = mpsc::channel::<LoadedFile>(CHANNEL_CAPACITY);
+    let (text_sender, text_receiver) = mpsc::channel::<LoadedFile>(CHANNEL_CAPACITY);
+
+    let load = extractor.load_files(file_paths, loaded_sender);
+    let archive = archiver.archive(output_path, text_receiver);
+
+    let select_text_files = async move {
+        let mut file_data_list = Vec::new();
+        let mut text_sender = Some(text_sender);
+
+        while let Some(loaded_file) = loaded_receiver.recv().await {
+            file_data_list.push(loaded_file.data.clone());
+
+            if loaded_file.data.error.is_some() {
+                if text_sender.take().is_some() {
+                    info!("run: error found, no more files are archived");
+                }
+                continue;
+            }
+
+            if !is_archived(&loaded_file) {
+                continue;
+            }
+
+            if let Some(sender) = &text_sender {
+                if sender.send(loaded_file).await.is_err() {
+                    // the archiver has stopped, its result tells why
+                    text_sender = None;
+                }
+            }
+        }
+
+        file_data_list
+    };
+
+    let (load_result, file_data_list, archive_result) =
+        tokio::join!(load, select_text_files, archive);
+    load_result?;
+
+    Ok(PipelineOutcome { file_data_list, archive_result })
+}
+
+/// Loads every file without archiving it and returns the data of the files
+/// that [`run`] would archive, for when they are listed before the archive is
+/// written. Every file is read, so this costs as much as loading them again.
+pub async fn list_archived_files(
+    extractor: &dyn FileDataExtractor,
+    file_paths: &[PathBuf],
+) -> Result<Vec<FileData>, Box<dyn Error + Send + Sync>> {
+    let (loaded_sender, mut loaded_receiver) = mpsc::channel::<LoadedFile>(CHANNEL_CAPACITY);
+    let load = extractor.load_files(file_paths, loaded_sender);
+
+    let select_archived_files = async move {
+        let mut file_data_list = Vec::new();
+        while let Some(loaded_file) = loaded_receiver.recv().await {
+            if loaded_file.data.error.is_none() && is_archived(&loaded_file) {
+                file_data_list.push(loaded_file.data);
+            }
+        }
+        file_data_list
+    };
+
+    let (load_result, file_data_list) = tokio::join!(load, select_archived_files);
+    load_result?;
+
+    Ok(file_data_list)
+}
+
+/// Checks if a file that was loaded without errors goes into the archive,
+/// binaries only do if the extractor loaded their content
+fn is_archived(loaded_file: &LoadedFile) -> bool {
+    loaded_file.data.is_text == Some(true) || !loaded_file.content.is_empty()
+}